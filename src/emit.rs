@@ -1,40 +1,420 @@
 use std::io::Write;
 
 use crate::{
-    ir::{Ir, RichToken},
-    lex::Token,
-    JUNK,
+    attribute_junk, has_empty_statement,
+    ir::{pad_literal, Ir, RichToken, FRAME_SIDE},
+    item_junk, junk_capacity,
+    lex::{Spanned, Token},
+    split::{split_raw, split_string},
+    Config, Overflow, SafeLen, Stats,
 };
 
 fn block_len(block: &[RichToken]) -> usize {
     block.iter().map(|token| token.len()).sum::<usize>()
 }
 
-/// Unformat into a rectangle
-pub fn block(writer: &mut impl Write, ir: &Ir, width: usize) {
+/// On average, one in this many anchors starts a new region in stable mode.
+const ANCHOR_STRIDE: u64 = 4;
+
+fn has_tokens(tokens: &[RichToken]) -> bool {
+    tokens
+        .iter()
+        .any(|token| matches!(token, RichToken::Token(_)))
+}
+
+/// Unformat into a rectangle, or into one rectangle per top-level item
+/// separated by blank lines.
+///
+/// ## Errors
+/// Returns an error if the code doesn't fit in `config.height` lines, if the
+/// width is too small for a frame or an acrostic, if there's a literal spanning
+/// several lines to frame or to spell an acrostic around, or if a token is too
+/// wide and the overflow policy is [`Overflow::Error`].
+pub fn unformat(writer: &mut impl Write, ir: &Ir, config: &Config) -> anyhow::Result<Stats> {
+    let (mut width, border) = match config.frame {
+        true => {
+            anyhow::ensure!(
+                !spans_lines(ir.tokens()),
+                "a frame can't go around literals that span several lines"
+            );
+            anyhow::ensure!(
+                config.width > 2 * FRAME_SIDE.len(),
+                "a frame needs a width of at least {}",
+                2 * FRAME_SIDE.len() + 1
+            );
+            (config.width - 2 * FRAME_SIDE.len(), 2)
+        }
+        false => (config.width, 0),
+    };
+
+    let widest = ir
+        .tokens()
+        .iter()
+        .filter(|token| matches!(token, RichToken::Token(_)))
+        .map(|token| widest_line(&token.as_str()))
+        .max()
+        .unwrap_or(0);
+    match config.overflow {
+        Overflow::Allow | Overflow::Split => {}
+        Overflow::Error => anyhow::ensure!(
+            widest <= width,
+            "a token is {widest} characters wide, more than the width of {width}"
+        ),
+        Overflow::Widen => width = width.max(widest),
+    }
+    let outer = match config.frame {
+        true => width + 2 * FRAME_SIDE.len(),
+        false => width,
+    };
+
+    let items = if config.item_rectangles {
+        ir.tokens()
+            .split(|token| matches!(token, RichToken::Boundary { .. }))
+            .filter(|item| has_tokens(item))
+            .collect()
+    } else {
+        vec![ir.tokens()]
+    };
+
+    let mut lines = vec![];
+    for (i, item) in items.into_iter().enumerate() {
+        if i != 0 {
+            lines.push(vec![]);
+        }
+
+        let mut rectangle = vec![];
+        if let Some(message) = &config.acrostic {
+            anyhow::ensure!(width >= 4, "an acrostic needs a width of at least 4");
+            anyhow::ensure!(
+                !spans_lines(item),
+                "an acrostic can't go around literals that span several lines"
+            );
+            rectangle.extend(acrostic(item, width, message, config));
+        } else if config.stable {
+            for region in anchored_regions(item) {
+                rectangle.extend(block(region, width, config));
+            }
+        } else {
+            rectangle.extend(block(item, width, config));
+        }
+
+        if let Some(height) = config.height {
+            fill_height(
+                &mut rectangle,
+                width,
+                height,
+                border,
+                config.max_junk.is_none(),
+            )?;
+        }
+
+        if config.frame {
+            frame(&mut rectangle, outer);
+        }
+
+        lines.extend(rectangle);
+    }
+
+    let stats = Stats {
+        width: outer,
+        lines: physical_lines(&lines),
+        comments: lines
+            .iter()
+            .flatten()
+            .filter(|token| {
+                matches!(
+                    token,
+                    RichToken::EndOfLineComment(_) | RichToken::InlineComment(_)
+                )
+            })
+            .count(),
+        filler: lines
+            .iter()
+            .flatten()
+            .map(|token| match token {
+                RichToken::Token(_) | RichToken::Piece(_) | RichToken::Segment { .. } => 0,
+                RichToken::PaddedLiteral { pad, .. } => *pad,
+                RichToken::RawIdent(_) => 2,
+                _ => token.len(),
+            })
+            .sum(),
+    };
+
+    for line in lines {
+        for token in line {
+            writer.write_all(&token.as_bytes()).unwrap();
+        }
+        writer.write_all(b"\n").unwrap()
+    }
+    writer.flush().unwrap();
+
+    Ok(stats)
+}
+
+/// Whether a literal in `tokens` spans several lines. Nothing can go at the
+/// start of a line inside a literal, and a line that's all literal can't be
+/// padded.
+fn spans_lines(tokens: &[RichToken]) -> bool {
+    tokens
+        .iter()
+        .any(|token| matches!(token, RichToken::Token(_)) && token.as_str().contains('\n'))
+}
+
+/// Length of the longest line in a token.
+fn widest_line(text: &str) -> usize {
+    text.lines().map(|line| line.safe_len()).max().unwrap_or(0)
+}
+
+/// Number of lines once printed, as literals can span several lines by
+/// themselves.
+fn physical_lines(lines: &[Vec<RichToken>]) -> usize {
+    lines.len()
+        + lines
+            .iter()
+            .flatten()
+            .map(|token| token.as_str().matches('\n').count())
+            .sum::<usize>()
+}
+
+/// Add lines of filler until there are exactly `height` lines, counting
+/// `border` lines that will be added around them.
+///
+/// Filler lines are spread evenly between statements, where they can be junk
+/// if `junk` is set. Otherwise, they are spread evenly between all lines as
+/// comments.
+fn fill_height(
+    lines: &mut Vec<Vec<RichToken>>,
+    width: usize,
+    height: usize,
+    border: usize,
+    junk: bool,
+) -> anyhow::Result<()> {
+    let needed = physical_lines(lines) + border;
+    anyhow::ensure!(
+        needed <= height,
+        "code needs {needed} lines, more than the height of {height}"
+    );
+
+    // Filler can go before any line, or at the very end, but not inside a
+    // string split across lines
+    let gaps = (0..=lines.len())
+        .filter(|&gap| gap == 0 || !ends_inside_literal(&lines[gap - 1]))
+        .collect::<Vec<_>>();
+    let junk_gaps = gaps
+        .iter()
+        .copied()
+        .filter(|&gap| {
+            (gap > 0 && ends_in_junk(&lines[gap - 1]))
+                || lines.get(gap).is_some_and(|line| starts_with_junk(line))
+        })
+        .collect::<Vec<_>>();
+    let (gaps, junk) = match !junk || junk_gaps.is_empty() {
+        true => (gaps, false),
+        false => (junk_gaps, true),
+    };
+
+    let extra = height - needed;
+    let filler = match junk {
+        true => junk_line(width),
+        false => vec![RichToken::EndOfLineComment(width.saturating_sub(2))],
+    };
+
+    // Insert from the back so earlier gaps stay at the same index
+    for i in (0..extra).rev() {
+        let gap = gaps[i * gaps.len() / extra];
+        lines.insert(gap, filler.clone());
+    }
+
+    Ok(())
+}
+
+/// A line of junk `width` wide. Junk of some lengths has an empty statement,
+/// so the line ends in a space or a comment if it has to.
+fn junk_line(width: usize) -> Vec<RichToken<'static>> {
+    let len = (0..=width)
+        .rev()
+        .find(|&len| !has_empty_statement(len))
+        .expect("empty junk has no empty statement");
+    let mut line = vec![RichToken::Junk(len)];
+    match width - len {
+        0 => {}
+        1 => line.push(RichToken::Spacer),
+        rest => line.push(RichToken::EndOfLineComment(rest - 2)),
+    }
+    line
+}
+
+/// Surround lines with a border of comments, `width` wide in total.
+fn frame(lines: &mut Vec<Vec<RichToken>>, width: usize) {
+    for line in lines.iter_mut() {
+        // An empty // would become /// with the border after it
+        if let Some(RichToken::EndOfLineComment(0)) = line.last() {
+            line.pop();
+            line.extend([RichToken::Spacer, RichToken::Spacer]);
+        }
+        line.insert(0, RichToken::FrameSide);
+        line.push(RichToken::FrameSide);
+    }
+    lines.insert(0, vec![RichToken::FrameEdge(width)]);
+    lines.push(vec![RichToken::FrameEdge(width)]);
+}
+
+/// Whether a line ends partway through a literal, so nothing can go after it.
+fn ends_inside_literal(line: &[RichToken]) -> bool {
+    line.iter()
+        .rev()
+        .find(|token| !token.is_empty())
+        .is_some_and(|token| match token {
+            RichToken::Piece(text) => text.ends_with('\\'),
+            RichToken::Segment { last, .. } => !last,
+            _ => false,
+        })
+}
+
+/// Whether a line starts partway through a literal, so nothing can go before
+/// it.
+fn starts_inside_literal(line: &[RichToken]) -> bool {
+    line.iter()
+        .find(|token| !token.is_empty())
+        .is_some_and(|token| matches!(token, RichToken::Segment { first: false, .. }))
+}
+
+fn starts_with_junk(line: &[RichToken]) -> bool {
+    line.iter()
+        .find(|token| is_junk(token) || !token.is_empty())
+        .is_some_and(|token| is_junk(token))
+}
+
+fn ends_in_junk(line: &[RichToken]) -> bool {
+    line.iter()
+        .rev()
+        .find(|token| {
+            is_junk(token) || !(token.is_empty() || matches!(token, RichToken::EndOfLineComment(_)))
+        })
+        .is_some_and(|token| is_junk(token))
+}
+
+fn is_junk(token: &RichToken) -> bool {
+    matches!(token, RichToken::Junk(_))
+}
+
+/// Split tokens into regions that are laid out independently.
+///
+/// Regions always start at top-level items. Other anchors are picked by
+/// hashing the tokens since the previous anchor, so whether a break happens
+/// only depends on nearby code and an edit can only move the breaks around it.
+fn anchored_regions<'t, 'a>(tokens: &'t [RichToken<'a>]) -> Vec<&'t [RichToken<'a>]> {
+    let mut regions = vec![];
+    let mut start = 0;
+    let mut hash = Fnv::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            RichToken::Boundary { .. } | RichToken::Anchor => {
+                let anchored = matches!(token, RichToken::Boundary { .. })
+                    || hash.finish() % ANCHOR_STRIDE == 0;
+                if anchored && has_tokens(&tokens[start..i]) {
+                    regions.push(&tokens[start..i]);
+                    start = i;
+                }
+                hash = Fnv::new();
+            }
+            RichToken::Token(token) => hash.write(token.inner.as_str().as_bytes()),
+            _ => {}
+        }
+    }
+    regions.push(&tokens[start..]);
+
+    regions
+}
+
+/// FNV-1a, which unlike the standard library's hasher is guaranteed to give
+/// the same output everywhere.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Unformat into the lines of a rectangle
+fn block<'a>(tokens: &[RichToken<'a>], width: usize, config: &Config) -> Vec<Vec<RichToken<'a>>> {
+    let split = config.overflow == Overflow::Split;
     let mut blocks = vec![];
 
-    let mut tokens = ir.tokens().to_vec();
+    let mut tokens = match split {
+        true => split_raw_strings(tokens, width),
+        false => tokens.to_vec(),
+    };
     tokens.reverse();
 
+    // Only literals whose spelling doesn't matter can be split, which are the
+    // ones that can be padded
+    let mut marked = false;
+
     // Don't exceed this point; we can always fill with a comment
     while !tokens.is_empty() {
         let mut block = vec![];
         let mut len = 0;
 
         while let Some(token) = tokens.pop() {
+            let splittable =
+                split && std::mem::replace(&mut marked, matches!(token, RichToken::PadLiteral));
+            if let Some((pieces, fresh)) = splittable
+                .then(|| split_literal(&token, len, width))
+                .flatten()
+            {
+                if fresh && !block.is_empty() {
+                    blocks.push(std::mem::take(&mut block));
+                }
+                // Every piece but the last fills a line exactly
+                let mut pieces = pieces.into_iter().map(RichToken::Piece).collect::<Vec<_>>();
+                let last = pieces.pop().expect("there is always a last piece");
+                for piece in pieces {
+                    block.push(piece);
+                    blocks.push(std::mem::take(&mut block));
+                    len = 0;
+                }
+                tokens.push(last);
+                continue;
+            }
+
+            // Literals spanning several lines are laid out a line at a time
+            if let Some(segments) = token.segments() {
+                tokens.extend(segments.into_iter().rev());
+                continue;
+            }
+            let ends_line = matches!(token, RichToken::Segment { last: false, .. });
+
             // If token itself is longer than limit, end previous line, and add
             // another line with just the token
             if token.len() >= width {
-                blocks.push(block);
+                if !block.is_empty() {
+                    blocks.push(block);
+                }
                 block = vec![token];
                 break;
             }
 
             if len + token.len() < width {
                 // Happy case, we can add token to line
-                block.push(token);
                 len += token.len();
+                block.push(token);
+                if ends_line {
+                    break;
+                }
             } else {
                 // Token overflows line, push it back to the stream and end the
                 // line
@@ -48,20 +428,266 @@ pub fn block(writer: &mut impl Write, ir: &Ir, width: usize) {
 
     // Get each block as close as possible to width
     for block in blocks.iter_mut() {
-        adjust_block(block, width);
+        adjust_block(block, width, config);
     }
 
-    for block in blocks {
-        for token in block {
-            writer.write_all(&token.as_bytes()).unwrap();
+    blocks
+}
+
+/// Split a string literal that's too long or spans several lines, starting
+/// after `len` characters of the current line if there's room and on a fresh
+/// line otherwise, which is reported by the second return value.
+fn split_literal(token: &RichToken, len: usize, width: usize) -> Option<(Vec<String>, bool)> {
+    let RichToken::Token(inner) = token else {
+        return None;
+    };
+    if !matches!(inner.inner, Token::Literal(_))
+        || (token.len() < width && !token.as_str().contains('\n'))
+    {
+        return None;
+    }
+
+    let text = token.as_str();
+    if let Some(pieces) = split_string(&text, width - len, width) {
+        return Some((pieces, false));
+    }
+    if len > 0 {
+        return split_string(&text, width, width).map(|pieces| (pieces, true));
+    }
+    None
+}
+
+/// Replace raw strings that are too long or span several lines with a
+/// concat! of raw strings that fit.
+fn split_raw_strings<'a>(tokens: &[RichToken<'a>], width: usize) -> Vec<RichToken<'a>> {
+    let mut out = vec![];
+    let mut concat = false;
+    for token in tokens {
+        match token {
+            RichToken::Concat => concat = true,
+            RichToken::Token(_) if concat => {
+                concat = false;
+                let text = token.as_str();
+                let pieces = (token.len() >= width || text.contains('\n'))
+                    .then(|| split_raw(&text, width))
+                    .flatten();
+                match pieces {
+                    Some(pieces) => {
+                        out.push(RichToken::Text("concat!("));
+                        out.extend(pieces.into_iter().map(RichToken::Piece));
+                        out.push(RichToken::Text(")"));
+                    }
+                    None => out.push(token.clone()),
+                }
+                continue;
+            }
+            _ => {}
         }
-        writer.write_all(&[b'\n']).unwrap()
+        out.push(token.clone());
     }
-    writer.flush().unwrap()
+    out
+}
+
+/// Unformat into the lines of a rectangle whose first visible characters spell
+/// `message`.
+///
+/// Lines are broken before a token that starts with the next character, or
+/// before junk that can start with it. Where neither is possible, the line
+/// ends by opening a comment that the next line closes with the character.
+fn acrostic<'a>(
+    tokens: &[RichToken<'a>],
+    width: usize,
+    message: &str,
+    config: &Config,
+) -> Vec<Vec<RichToken<'a>>> {
+    let mut letters = message.chars().filter(|c| !c.is_whitespace());
+
+    // Whether each token is in code evaluated at compile time
+    let mut depth = 0usize;
+    let in_const = tokens
+        .iter()
+        .map(|token| {
+            match token {
+                RichToken::ConstOpen => depth += 1,
+                RichToken::ConstClose => depth -= 1,
+                _ => {}
+            }
+            depth > 0
+        })
+        .collect::<Vec<_>>();
+
+    // Each line, and whether it ends by opening a comment
+    let mut lines: Vec<(Vec<RichToken>, bool)> = vec![];
+    let mut line = vec![];
+    let mut pos = 0;
+
+    // Nothing can go before the first token, so unless it matches, the first
+    // line has to close a comment opened on a line of its own
+    if let Some(c) = letters.next() {
+        let first = tokens
+            .iter()
+            .find(|token| matches!(token, RichToken::Token(_)));
+        if !first.is_some_and(|token| token.as_str().starts_with(c)) {
+            lines.push((vec![], true));
+            line.push(RichToken::CommentClose(c));
+        }
+    }
+
+    while pos < tokens.len() {
+        // Furthest the line can go
+        let mut len = block_len(&line);
+        let mut end = pos;
+        while end < tokens.len() && len + tokens[end].len() < width {
+            len += tokens[end].len();
+            end += 1;
+        }
+
+        // If the next token is longer than the limit, it gets a line to itself
+        if !has_tokens(&tokens[pos..end]) {
+            end = tokens[end..]
+                .iter()
+                .position(|token| matches!(token, RichToken::Token(_)))
+                .map_or(tokens.len(), |i| end + i + 1);
+        }
+
+        let Some(c) = letters.next() else {
+            line.extend_from_slice(&tokens[pos..end]);
+            lines.push((std::mem::take(&mut line), false));
+            pos = end;
+            continue;
+        };
+
+        // Latest break before a token starting with c, or before junk, that
+        // leaves some code on this line
+        let brk = (pos..=end.min(tokens.len() - 1)).rev().find(|&i| {
+            let starts = match tokens[i] {
+                RichToken::Token(_) => tokens[i].as_str().starts_with(c),
+                RichToken::Junk(_) => lead(c, in_const[i]).is_some(),
+                _ => false,
+            };
+            starts && has_tokens(&tokens[pos..i])
+        });
+
+        match brk {
+            Some(i) => {
+                line.extend_from_slice(&tokens[pos..i]);
+                lines.push((std::mem::take(&mut line), false));
+                if let (RichToken::Junk(_), Some(lead)) = (&tokens[i], lead(c, in_const[i])) {
+                    line.push(RichToken::Text(lead));
+                }
+                pos = i;
+            }
+            None => {
+                // Leave room to open the comment
+                while end > pos
+                    && block_len(&line) + block_len(&tokens[pos..end]) + 2 >= width
+                    && has_tokens(&tokens[pos..end - 1])
+                {
+                    end -= 1;
+                }
+                line.extend_from_slice(&tokens[pos..end]);
+                lines.push((std::mem::take(&mut line), true));
+                line.push(RichToken::CommentClose(c));
+                pos = end;
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push((line, false));
+    }
+
+    // Spell whatever is left after the code runs out with comments
+    for c in letters {
+        match lines.last_mut() {
+            Some((_, opens)) => *opens = true,
+            None => lines.push((vec![], true)),
+        }
+        lines.push((vec![RichToken::CommentClose(c)], false));
+    }
+
+    lines
+        .into_iter()
+        .map(|(mut line, opens)| {
+            if opens {
+                adjust_block(&mut line, width - 2, config);
+                if let Some(RichToken::EndOfLineComment(_)) = line.last() {
+                    line.pop();
+                }
+                let len = block_len(&line);
+                line.push(RichToken::CommentOpen(width.saturating_sub(len).max(2)));
+            } else {
+                adjust_block(&mut line, width, config);
+            }
+            line
+        })
+        .collect()
+}
+
+/// Junk statement starting with `c`, if there is one. Code evaluated at
+/// compile time can't call functions that aren't `const`.
+fn lead(c: char, in_const: bool) -> Option<&'static str> {
+    let lead = match c {
+        'd' if in_const => "debug_assert!(true);",
+        '<' if in_const => "<u8>::MAX;",
+        'e' | 'p' | 'v' | 'A' | 'B' | 'C' | 'I' | 'P' | 'T' | 'V' if in_const => return None,
+        '0' => "0;",
+        '1' => "1;",
+        '2' => "2;",
+        '3' => "3;",
+        '4' => "4;",
+        '5' => "5;",
+        '6' => "6;",
+        '7' => "7;",
+        '8' => "8;",
+        '9' => "9;",
+        'a' => "assert!(true);",
+        'b' => "b\"\";",
+        'c' => "char::MAX;",
+        'd' => "drop(());",
+        'e' => "eprint!(\"\");",
+        'f' => "false;",
+        'i' => "if true{};",
+        'l' => "line!();",
+        'm' => "matches!((),());",
+        'o' => "option_env!(\"o\");",
+        'p' => "print!(\"\");",
+        'r' => "r\"\";",
+        's' => "str::len(\"\");",
+        't' => "true;",
+        'u' => "u8::MAX;",
+        'v' => "vec![0];",
+        'w' => "while false{};",
+        'A' => "AsRef::<str>::as_ref(\"\");",
+        'B' => "Box::new(());",
+        'C' => "Clone::clone(&());",
+        'E' => "Err::<(),()>(());",
+        'I' => "Iterator::count(0..0);",
+        'N' => "None::<()>;",
+        'O' => "Ok::<(),()>(());",
+        'P' => "PartialEq::eq(&(),&());",
+        'S' => "Some(());",
+        'T' => "ToString::to_string(\"\");",
+        'V' => "Vec::<()>::new();",
+        '(' => "();",
+        '{' => "{};",
+        '[' => "[0;0];",
+        '"' => "\"\";",
+        '\'' => "'a';",
+        '-' => "-1;",
+        '!' => "!0;",
+        '*' => "*&0;",
+        '&' => "&0;",
+        '|' => "||();",
+        '_' => "_=();",
+        '<' => "<()>::default();",
+        ';' => ";",
+        _ => return None,
+    };
+    Some(lead)
 }
 
 /// Adjust a block to as close to width characters as possible
-fn adjust_block(block: &mut Vec<RichToken>, width: usize) {
+fn adjust_block(block: &mut Vec<RichToken>, width: usize, config: &Config) {
     // Remove leading and trailing spacers
     while let Some(RichToken::Spacer) = block.first() {
         block.remove(0);
@@ -70,35 +696,100 @@ fn adjust_block(block: &mut Vec<RichToken>, width: usize) {
         block.pop();
     }
 
-    // Add in junk
-    adjust_stmts(block, width);
+    // A slash at the end of the line would fuse with whatever comes after it,
+    // so keep a space between them
+    if ends_in_slash(block) {
+        block.push(RichToken::Spacer);
+    }
+
+    let junks = junk_slots(block, config);
+    let exprs = expr_slots(block);
+    let spellings = spelling_slots(block, config);
+
+    if config.spread {
+        // Parentheses can only add characters in pairs, so if we're an odd
+        // number away and nothing else can make up the difference, use
+        // optional text
+        if junks.is_empty() && !exprs.is_empty() && width.saturating_sub(block_len(block)) % 2 == 1
+        {
+            adjust_optionals_by(block, 1);
+        }
 
-    // Add in exprs
-    adjust_exprs(block, width);
+        // Take turns adding junk and parentheses
+        let mut slots = junks
+            .into_iter()
+            .chain(exprs)
+            .chain(spellings)
+            .collect::<Vec<_>>();
+        slots.sort_by_key(|slot| match slot {
+            Slot::Junk(i)
+            | Slot::Sized(i)
+            | Slot::Block(i, _)
+            | Slot::Expr(i, _)
+            | Slot::Literal(i)
+            | Slot::Ident(i)
+            | Slot::Shorthand(i) => *i,
+        });
+        let diff = width.saturating_sub(block_len(block));
+        grow(block, &slots, diff, config);
+    } else {
+        // Add in junk
+        let diff = width.saturating_sub(block_len(block));
+        grow(block, &junks, diff, config);
+
+        // Parentheses can only add characters in pairs, so if we're an odd
+        // number away, make up the difference with optional text
+        if !exprs.is_empty() && width.saturating_sub(block_len(block)) % 2 == 1 {
+            adjust_optionals_by(block, 1);
+        }
+
+        // Add in exprs
+        let diff = width.saturating_sub(block_len(block));
+        grow(block, &exprs, diff, config);
+
+        // Make up whatever parentheses couldn't with longer literals and raw
+        // identifiers
+        let diff = width.saturating_sub(block_len(block));
+        grow(block, &spellings, diff, config);
+    }
+
+    // Fill whatever is left over with optional text
+    let diff = width.saturating_sub(block_len(block));
+    adjust_optionals_by(block, diff);
 
     // If we're one away, add a space in the middle.
     let len = block_len(block);
-    if len == width - 1 {
-        block.insert(block.len() / 2, RichToken::Spacer);
+    let middle = match starts_inside_literal(block) {
+        true => block.len().div_ceil(2),
+        false => block.len() / 2,
+    };
+    if len == width - 1 && middle < block.len() {
+        block.insert(middle, RichToken::Spacer);
     }
 
-    // Add comments to end of line
+    // Add comments to end of line. If it ends inside a literal, the comment
+    // goes before the literal opens instead
     let len = block_len(block);
-    if len < width && !ends_in_slash(block) {
+    if len < width && !ends_inside_literal(block) {
         let comment_text_len = (width - len).saturating_sub(2);
-        block.push(RichToken::EndOfLineComment(
-            JUNK[comment_text_len.min(JUNK.len() - 1)],
-        ));
+        block.push(RichToken::EndOfLineComment(comment_text_len));
+    } else if len < width {
+        let opening = block
+            .iter()
+            .rposition(|token| matches!(token, RichToken::Segment { first: true, .. }));
+        if let Some(i) = opening {
+            block.insert(i, RichToken::InlineComment(width - len));
+        }
     }
 }
 
 fn ends_in_slash(block: &[RichToken]) -> bool {
-    let mut i = block.len() - 1;
     // Ignore control tokens
-    while i > 0 && !matches!(block.get(i).unwrap(), RichToken::Token(_)) {
-        i -= 1;
-    }
-    is_slash(block.get(i).unwrap())
+    block
+        .iter()
+        .rev()
+        .find(|token| matches!(token, RichToken::Token(_)) || !token.is_empty())
+        .is_some_and(is_slash)
 }
 
 fn is_slash(token: &RichToken) -> bool {
@@ -109,110 +800,299 @@ fn is_slash(token: &RichToken) -> bool {
     }
 }
 
-fn adjust_stmts(block: &mut [RichToken], width: usize) {
-    let len = block_len(block);
-
-    let junks = block
-        .iter()
-        .enumerate()
-        .filter_map(|(i, token)| {
-            if let RichToken::Junk(_) = token {
-                Some(i)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
-
-    if junks.is_empty() {
-        return;
-    }
-
-    let diff = width.saturating_sub(len);
-    adjust_stmts_by(block, diff, &junks);
+/// A place on a line that padding can go.
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    /// Junk at this index
+    Junk(usize),
+    /// Junk items or attributes at this index, which can only have some
+    /// lengths
+    Sized(usize),
+    /// Junk in a block opened and closed at these indices
+    Block(usize, usize),
+    /// Parentheses opened and closed at these indices
+    Expr(usize, usize),
+    /// A literal at this index that can be written longer
+    Literal(usize),
+    /// An identifier at this index that can be made raw
+    Ident(usize),
+    /// A shorthand field at this index that can be spelled out
+    Shorthand(usize),
 }
 
-fn adjust_stmts_by(block: &mut [RichToken], n: usize, junks: &[usize]) {
-    let per_junk = n / junks.len();
-    let extra = n % junks.len();
-
-    for junk in junks {
-        if let RichToken::Junk(n) = block.get_mut(*junk).unwrap() {
-            *n += per_junk;
-        } else {
-            panic!("we already checked this is a junk")
-        }
-    }
-
-    for junk in junks.iter().take(extra) {
-        if let RichToken::Junk(n) = block.get_mut(*junk).unwrap() {
-            *n += 1;
-        } else {
-            panic!("we already checked this is a junk")
+/// Junk on a line. With a limit on consecutive junk statements, junk right
+/// next to other junk is left out, so that each run of junk grows in one place.
+fn junk_slots(block: &[RichToken], config: &Config) -> Vec<Slot> {
+    let mut junks = vec![];
+    let mut last = None;
+    for (i, token) in block.iter().enumerate() {
+        let slot = match token {
+            RichToken::Junk(_) => Slot::Junk(i),
+            RichToken::ItemJunk { .. } | RichToken::AttributeJunk(_) => Slot::Sized(i),
+            RichToken::BlockOpen { id, .. } => {
+                let close = block.iter().skip(i + 1).position(|token| {
+                    matches!(token, RichToken::BlockClose { id: close_id, .. } if close_id == id)
+                });
+                // The block has to close on this line too
+                match close {
+                    Some(j) => Slot::Block(i, i + 1 + j),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let adjacent = last.is_some_and(|last: usize| {
+            block[last + 1..i]
+                .iter()
+                .all(|token| !matches!(token, RichToken::Token(_)) && token.is_empty())
+        });
+        if config.max_junk.is_none() || !adjacent {
+            junks.push(slot);
         }
+        last = Some(i);
     }
+    junks
 }
 
-fn adjust_exprs(block: &mut [RichToken], width: usize) {
-    let len = block_len(block);
-
+/// Parentheses on a line, and shorthand fields that can be spelled out to get
+/// parentheses around their value.
+fn expr_slots(block: &[RichToken]) -> Vec<Slot> {
     let mut exprs = vec![];
     for (i, token) in block.iter().enumerate() {
+        if let RichToken::Shorthand { .. } = token {
+            exprs.push(Slot::Shorthand(i));
+        }
         if let RichToken::ExprOpen { id, .. } = token {
             for (j, close) in block.iter().enumerate().skip(i + 1) {
                 if let RichToken::ExprClose { id: close_id, .. } = close {
                     if id == close_id {
-                        exprs.push((i, j))
+                        exprs.push(Slot::Expr(i, j))
                     }
                 }
             }
         }
     }
-    if exprs.is_empty() {
-        return;
-    }
-
-    let diff = width.saturating_sub(len);
+    exprs
+}
 
-    if diff % 2 == 0 {
-        adjust_exprs_by(block, diff, &exprs)
-    } else {
-        // Difference is odd, and we can only add an even number of characters.
-        // Leave space for an end of line comment
-        adjust_exprs_by(block, diff, &exprs);
+/// Literals and identifiers on a line that can be spelled longer. The first
+/// character of a line in an acrostic is spoken for, so a token that starts the
+/// line is left alone.
+fn spelling_slots(block: &[RichToken], config: &Config) -> Vec<Slot> {
+    let first = block.iter().position(|token| !token.is_empty());
+    let mut slots = vec![];
+    for (i, token) in block.iter().enumerate() {
+        if config.acrostic.is_some() && first.map_or(true, |first| first >= i) {
+            continue;
+        }
+        let before = i.checked_sub(1).map(|before| &block[before]);
+        let slot = match (before, token) {
+            (
+                Some(RichToken::PadLiteral),
+                RichToken::Token(Spanned {
+                    inner: Token::Literal(_),
+                    ..
+                }),
+            ) => Slot::Literal(i),
+            (
+                Some(RichToken::PadIdent),
+                RichToken::Token(Spanned {
+                    inner: Token::Ident(_),
+                    ..
+                }),
+            ) => Slot::Ident(i),
+            _ => continue,
+        };
+        slots.push(slot);
     }
+    slots
 }
 
-/// Adjust exprs to add `n` characters.
-fn adjust_exprs_by(block: &mut [RichToken], n: usize, exprs: &[(usize, usize)]) {
-    let iters = n / 2; // each addition adds two characters, ( and )
-
-    let per_expr = iters / exprs.len();
-    let extra = iters % exprs.len();
+/// Turn on optional text to add at most `n` characters.
+fn adjust_optionals_by(block: &mut [RichToken], mut n: usize) {
+    for token in block.iter_mut() {
+        if let RichToken::Optional {
+            choices,
+            chosen: chosen @ None,
+        } = token
+        {
+            // The longest choice that fits
+            if let Some((i, text)) = choices
+                .iter()
+                .enumerate()
+                .filter(|(_, text)| text.len() <= n)
+                .max_by_key(|(_, text)| text.len())
+            {
+                *chosen = Some(i);
+                n -= text.len();
+            }
+        }
+    }
+}
 
-    for (open, close) in exprs {
-        if let RichToken::ExprOpen { reps, .. } = block.get_mut(*open).unwrap() {
-            *reps += per_expr;
-        } else {
-            panic!("we already checked this is an expropen")
+/// Item or attribute junk resized to `len`, if it can be that long.
+fn resized<'a>(token: &RichToken<'a>, len: usize) -> Option<RichToken<'a>> {
+    match *token {
+        RichToken::ItemJunk { ty, .. } => {
+            item_junk(len, ty).map(|_| RichToken::ItemJunk { len, ty })
         }
-        if let RichToken::ExprClose { reps, .. } = block.get_mut(*close).unwrap() {
-            *reps += per_expr;
-        } else {
-            panic!("we already checked this is an exprclose",)
+        RichToken::AttributeJunk(_) => attribute_junk(len).map(|_| RichToken::AttributeJunk(len)),
+        _ => None,
+    }
+}
+
+/// Number of items or attributes in a piece of junk.
+fn junk_pieces(token: &RichToken) -> usize {
+    let text = token.as_str();
+    match token {
+        RichToken::ItemJunk { .. } => {
+            text.matches("const _").count() + text.matches("impl ").count()
         }
+        _ => text.matches("#[").count(),
     }
+}
 
-    for (open, close) in exprs.iter().take(extra) {
-        if let RichToken::ExprOpen { reps, .. } = block.get_mut(*open).unwrap() {
-            *reps += 1;
-        } else {
-            panic!("we already checked this is an expropen")
+/// Length that junk `len` characters long can grow to with at most `n` more,
+/// up to `max_junk`. A lone `;` is an empty statement, so lengths with one are
+/// skipped.
+fn next_junk(len: usize, n: usize, max_junk: usize) -> Option<usize> {
+    (len + 1..=(len + n).min(max_junk)).find(|&next| !has_empty_statement(next))
+}
+
+/// Add at most `n` characters to slots, taking turns so padding is spread
+/// evenly. Each turn junk, junk items, attributes and literals grow to the next
+/// length they can have, expressions by a pair of parentheses, identifiers
+/// become raw and shorthand fields are spelled out, up to the limits in
+/// `config`.
+fn grow(block: &mut [RichToken], slots: &[Slot], mut n: usize, config: &Config) {
+    let max_junk = config.max_junk.map_or(usize::MAX, junk_capacity);
+    let max_parens = config.max_parens.unwrap_or(usize::MAX);
+    let max_pieces = config.max_junk.unwrap_or(usize::MAX);
+
+    loop {
+        let mut grew = false;
+        for slot in slots {
+            match *slot {
+                Slot::Junk(i) => {
+                    let RichToken::Junk(len) = &mut block[i] else {
+                        panic!("we already checked this is a junk")
+                    };
+                    if let Some(next) = next_junk(*len, n, max_junk) {
+                        n -= next - *len;
+                        *len = next;
+                        grew = true;
+                    }
+                }
+                Slot::Sized(i) => {
+                    // Not every length can be made of items or attributes,
+                    // so skip ahead to the next one that can
+                    let len = block[i].len();
+                    let next = (len + 1..=len + n)
+                        .find_map(|next| resized(&block[i], next))
+                        .filter(|token| junk_pieces(token) <= max_pieces);
+                    if let Some(token) = next {
+                        n -= token.len() - len;
+                        block[i] = token;
+                        grew = true;
+                    }
+                }
+                Slot::Block(open, close) => {
+                    let RichToken::BlockOpen { len, .. } = &mut block[open] else {
+                        panic!("we already checked this is a blockopen")
+                    };
+                    match len {
+                        // The braces and junk to go in them, since braces
+                        // around nothing but a value trip a lint, which junk
+                        // doesn't (see the `location` module)
+                        None => {
+                            let Some(next) =
+                                n.checked_sub(2).and_then(|n| next_junk(0, n, max_junk))
+                            else {
+                                continue;
+                            };
+                            n -= next + 2;
+                            *len = Some(next);
+                        }
+                        Some(len) => {
+                            let Some(next) = next_junk(*len, n, max_junk) else {
+                                continue;
+                            };
+                            n -= next - *len;
+                            *len = next;
+                        }
+                    }
+                    let RichToken::BlockClose { on, .. } = &mut block[close] else {
+                        panic!("we already checked this is a blockclose")
+                    };
+                    *on = true;
+                    grew = true;
+                }
+                Slot::Literal(i) => {
+                    let (text, pad) = match block[i] {
+                        RichToken::Token(Spanned {
+                            inner: Token::Literal(text),
+                            ..
+                        }) => (text, 0),
+                        RichToken::PaddedLiteral { text, pad } => (text, pad),
+                        _ => panic!("we already checked this is a literal"),
+                    };
+                    // Some literals only grow in steps of two, or not at all
+                    let next = (pad + 1..=pad + n).find(|&next| pad_literal(text, next).is_some());
+                    if let Some(next) = next {
+                        n -= next - pad;
+                        block[i] = RichToken::PaddedLiteral { text, pad: next };
+                        grew = true;
+                    }
+                }
+                Slot::Ident(i) => {
+                    let RichToken::Token(Spanned {
+                        inner: Token::Ident(ident),
+                        ..
+                    }) = block[i]
+                    else {
+                        continue;
+                    };
+                    if n >= 2 {
+                        block[i] = RichToken::RawIdent(ident);
+                        n -= 2;
+                        grew = true;
+                    }
+                }
+                Slot::Shorthand(i) => {
+                    let RichToken::Shorthand { ident, on, reps } = &mut block[i] else {
+                        panic!("we already checked this is a shorthand")
+                    };
+                    // Spelled out first, then with parentheses around the value
+                    if !*on && n > ident.len() {
+                        *on = true;
+                        n -= ident.len() + 1;
+                    } else if *on && n >= 2 && *reps < max_parens {
+                        *reps += 1;
+                        n -= 2;
+                    } else {
+                        continue;
+                    }
+                    grew = true;
+                }
+                Slot::Expr(open, close) => {
+                    let RichToken::ExprOpen { reps, once, .. } = &mut block[open] else {
+                        panic!("we already checked this is an expropen")
+                    };
+                    if n < 2 || *reps >= max_parens || (*once && *reps >= 1) {
+                        continue;
+                    }
+                    *reps += 1;
+                    let RichToken::ExprClose { reps, .. } = &mut block[close] else {
+                        panic!("we already checked this is an exprclose")
+                    };
+                    *reps += 1;
+                    n -= 2;
+                    grew = true;
+                }
+            }
         }
-        if let RichToken::ExprClose { reps, .. } = block.get_mut(*close).unwrap() {
-            *reps += 1;
-        } else {
-            panic!("we already checked this is an exprclose",)
+        if !grew {
+            break;
         }
     }
 }
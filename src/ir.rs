@@ -1,27 +1,112 @@
 use std::borrow::Cow;
 
 use crate::{
-    lex::{Spanned, Token},
+    attribute_junk, item_junk, junk,
+    lex::{self, Spanned, Token, TokenStart},
     location::Event,
-    SafeLen, JUNK,
+    SafeLen,
 };
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub const FRAME_SIDE: &str = "/*|*/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RichToken<'a> {
     Junk(usize),
+    /// Junk items of this length in a module, which can implement `ty`
+    ItemJunk {
+        len: usize,
+        ty: Option<&'a str>,
+    },
+    /// No-op attributes of this length
+    AttributeJunk(usize),
     /// Guaranteed space that separates two tokens can't fuse
     /// ex. - and > can't fuse as they would form a ->
     Spacer,
     Token(Spanned<Token<'a>>),
-    // // at end of line
-    EndOfLineComment(&'static str),
+    // // at end of line, with this many characters of text
+    EndOfLineComment(usize),
+    /// Parentheses, or braces around a use tree. Trait bounds can only be
+    /// wrapped `once`
     ExprOpen {
         id: usize,
         reps: usize,
+        brace: bool,
+        once: bool,
     },
     ExprClose {
         id: usize,
         reps: usize,
+        brace: bool,
+    },
+    /// { and this much junk before an expression, if it is wrapped in a block
+    BlockOpen {
+        id: usize,
+        len: Option<usize>,
+    },
+    /// } after an expression, if it is wrapped in a block
+    BlockClose {
+        id: usize,
+        on: bool,
+    },
+    /// Text that may be left out, such as a trailing comma, or one of several
+    /// texts that mean the same thing
+    Optional {
+        choices: &'static [&'static str],
+        chosen: Option<usize>,
+    },
+    /// Zero-width marker before a top-level item, which is pinned if it can't
+    /// be reordered
+    Boundary {
+        pinned: bool,
+    },
+    /// Zero-width marker before a statement or nested item, where a line
+    /// break may be anchored
+    Anchor,
+    /// Zero-width markers around code evaluated at compile time
+    ConstOpen,
+    ConstClose,
+    /// /*|*/ on either side of a framed line
+    FrameSide,
+    /// /***...*/ above and below a frame of this width
+    FrameEdge(usize),
+    /// Text that is always printed, such as junk starting with a particular
+    /// character
+    Text(&'static str),
+    /// /*---*/ of this length in the middle of a line, or spaces if that's too
+    /// short for a comment
+    InlineComment(usize),
+    /// /*--- of this length at the end of a line, closed by the next line
+    CommentOpen(usize),
+    /// c*/ at the start of a line, closing the comment from the line before
+    CommentClose(char),
+    /// Zero-width marker before a raw string that could be split with concat!
+    Concat,
+    /// Zero-width marker before a literal that can be padded or split
+    PadLiteral,
+    /// A literal written this many characters longer
+    PaddedLiteral {
+        text: &'a str,
+        pad: usize,
+    },
+    /// Zero-width marker before an identifier that can be made raw
+    PadIdent,
+    /// An identifier written as a raw identifier, like r#x
+    RawIdent(&'a str),
+    /// `: x` after the shorthand field `x`, if it is spelled out, with this
+    /// many pairs of parentheses around the value
+    Shorthand {
+        ident: &'a str,
+        on: bool,
+        reps: usize,
+    },
+    /// Part of a literal split across lines
+    Piece(String),
+    /// One physical line of a literal that spans several. Every segment but
+    /// the last ends a line, and every one but the first starts one
+    Segment {
+        text: &'a str,
+        first: bool,
+        last: bool,
     },
 }
 
@@ -32,33 +117,120 @@ impl<'a> From<Spanned<Token<'a>>> for RichToken<'a> {
 }
 
 impl<'a> RichToken<'a> {
-    pub fn as_bytes(&self) -> Cow<[u8]> {
-        // TODO: use as_str imple and as_bytes
-        match self {
-            RichToken::Junk(n) => Cow::Borrowed(JUNK[*n].as_bytes()),
-            RichToken::Spacer => Cow::Borrowed(b" "),
-            RichToken::Token(token) => Cow::Borrowed(token.inner.as_str().as_bytes()),
-            RichToken::EndOfLineComment(c) => Cow::Owned(format!("//{c}").into_bytes()),
-            RichToken::ExprOpen { reps, .. } => Cow::Owned(b"(".repeat(*reps)),
-            RichToken::ExprClose { reps, .. } => Cow::Owned(b")".repeat(*reps)),
+    pub fn as_bytes(&self) -> Cow<'_, [u8]> {
+        match self.as_str() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
         }
     }
 
-    pub fn as_str(&self) -> Cow<str> {
+    pub fn as_str(&self) -> Cow<'_, str> {
         match self {
-            RichToken::Junk(n) => Cow::Borrowed(JUNK[*n]),
+            RichToken::Junk(n) => junk(*n),
+            RichToken::ItemJunk { len, ty } => {
+                item_junk(*len, *ty).expect("item junk only grows to lengths it can have")
+            }
+            RichToken::AttributeJunk(len) => {
+                attribute_junk(*len).expect("attribute junk only grows to lengths it can have")
+            }
             RichToken::Spacer => Cow::Borrowed(" "),
             RichToken::Token(token) => Cow::Borrowed(token.inner.as_str()),
-            RichToken::EndOfLineComment(c) => Cow::Owned(format!("//{c}")),
-            RichToken::ExprOpen { reps, .. } => Cow::Owned("(".repeat(*reps)),
-            RichToken::ExprClose { reps, .. } => Cow::Owned(")".repeat(*reps)),
+            RichToken::EndOfLineComment(n) => Cow::Owned(format!("//{}", junk(*n))),
+            RichToken::ExprOpen { reps, brace, .. } => match brace {
+                true => Cow::Owned("{".repeat(*reps)),
+                false => Cow::Owned("(".repeat(*reps)),
+            },
+            RichToken::ExprClose { reps, brace, .. } => match brace {
+                true => Cow::Owned("}".repeat(*reps)),
+                false => Cow::Owned(")".repeat(*reps)),
+            },
+            RichToken::BlockOpen { len, .. } => match len {
+                Some(len) => Cow::Owned(format!("{{{}", junk(*len))),
+                None => Cow::Borrowed(""),
+            },
+            RichToken::BlockClose { on, .. } => match on {
+                true => Cow::Borrowed("}"),
+                false => Cow::Borrowed(""),
+            },
+            RichToken::Optional { choices, chosen } => match chosen {
+                Some(i) => Cow::Borrowed(choices[*i]),
+                None => Cow::Borrowed(""),
+            },
+            RichToken::Boundary { .. }
+            | RichToken::Anchor
+            | RichToken::ConstOpen
+            | RichToken::ConstClose
+            | RichToken::Concat
+            | RichToken::PadLiteral
+            | RichToken::PadIdent => Cow::Borrowed(""),
+            RichToken::PaddedLiteral { text, pad } => {
+                pad_literal(text, *pad).expect("literals only grow to lengths they can have")
+            }
+            RichToken::FrameSide => Cow::Borrowed(FRAME_SIDE),
+            RichToken::FrameEdge(width) => {
+                Cow::Owned(format!("/*{}*/", "*".repeat(width.saturating_sub(4))))
+            }
+            RichToken::Text(text) => Cow::Borrowed(text),
+            RichToken::InlineComment(n) => match n {
+                0..=3 => Cow::Owned(" ".repeat(*n)),
+                _ => Cow::Owned(format!("/*{}*/", "-".repeat(n - 4))),
+            },
+            RichToken::CommentOpen(n) => {
+                Cow::Owned(format!("/*{}", "-".repeat(n.saturating_sub(2))))
+            }
+            // /*/ would open a nested comment
+            RichToken::CommentClose('/') => Cow::Borrowed("/ */"),
+            RichToken::CommentClose(c) => Cow::Owned(format!("{c}*/")),
+            RichToken::RawIdent(ident) => Cow::Owned(format!("r#{ident}")),
+            RichToken::Shorthand { ident, on, reps } => match on {
+                true => Cow::Owned(format!(
+                    ":{}{ident}{}",
+                    "(".repeat(*reps),
+                    ")".repeat(*reps)
+                )),
+                false => Cow::Borrowed(""),
+            },
+            RichToken::Piece(text) => Cow::Borrowed(text),
+            RichToken::Segment { text, .. } => Cow::Borrowed(text),
         }
     }
 
-    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.as_str().as_ref().safe_len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Split a literal that spans several lines into the segments on each
+    /// line.
+    pub fn segments(&self) -> Option<Vec<RichToken<'a>>> {
+        let RichToken::Token(Spanned {
+            inner: Token::Literal(text),
+            ..
+        }) = self
+        else {
+            return None;
+        };
+        let text: &'a str = text;
+        let lines = text.split('\n').collect::<Vec<_>>();
+        if lines.len() == 1 {
+            return None;
+        }
+
+        let last = lines.len() - 1;
+        let segments = lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| RichToken::Segment {
+                text,
+                first: i == 0,
+                last: i == last,
+            })
+            .collect();
+        Some(segments)
+    }
 }
 
 #[derive(Debug)]
@@ -95,12 +267,15 @@ impl<'a> Ir<'a> {
                     rts.push(RichToken::Spacer);
                     rts.push(RichToken::Token(token));
                 }
-                // < and < combine to form << (can happen in macro)
-                (Token::LessThan, Token::LessThan)
-                // > and => combine to form >=> (can happen in macro)
-                // Same thing can happen with +, *, ?
-                | (Token::GreatherThan | Token::Plus|Token::Star|Token::Question, Token::FatArrow)
-                    => {
+                // > and => combine to form >=>, as in None::<u8> =>. Tokens in
+                // macros are spaced by the visitor
+                (Token::GreatherThan, Token::FatArrow) => {
+                    rts.push(RichToken::Spacer);
+                    rts.push(RichToken::Token(token));
+                }
+                // 1.cmp(&2) can't become 1.
+                // cmp(&2), since 1. would be a float
+                (Token::Literal(text), Token::Dot) if is_integer(text) => {
                     rts.push(RichToken::Spacer);
                     rts.push(RichToken::Token(token));
                 }
@@ -129,48 +304,135 @@ impl<'a> Ir<'a> {
     }
 
     /// Add in junk/expr tokens where needed.
-    pub fn populate_events(&self, mut events: &[Spanned<Event>]) -> Ir<'a> {
+    pub fn populate_events(&self, events: &[Spanned<Event>]) -> Ir<'a> {
         let mut out = vec![];
 
         // Handling expr open/close
         let mut next_id = 0;
         let mut expr_starts = vec![];
 
-        let tokens = self.tokens.iter().cloned();
+        // A type ending in > can be followed by another > or an =, which get
+        // lexed together. A reference to a reference starts with &&. Two
+        // tuple indices in a row get lexed as a float.
+        let tokens = self
+            .tokens
+            .iter()
+            .flat_map(|token| match token {
+                RichToken::Token(inner)
+                    if is_splittable(&inner.inner)
+                        && events.iter().any(|event| lands_in(event, inner)) =>
+                {
+                    lex::split_punctuation(*inner)
+                        .into_iter()
+                        .map(RichToken::Token)
+                        .collect()
+                }
+                RichToken::Token(inner)
+                    if is_tuple_indices(&inner.inner)
+                        && events
+                            .iter()
+                            .any(|event| lands_in(event, inner) && !event.aligns_with(inner)) =>
+                {
+                    lex::split_float(*inner)
+                        .into_iter()
+                        .map(RichToken::Token)
+                        .collect()
+                }
+                _ => vec![token.clone()],
+            })
+            .collect::<Vec<_>>();
+        let tokens = tokens.iter().cloned();
+
+        // The visitor doesn't always see nodes in source order (where clauses
+        // are visited before function arguments, for example). The sort is
+        // stable, so nested events at the same token keep their order.
+        let mut events = events.to_vec();
+        events.sort_by_key(|event| (event.region.line, event.region.char));
+
+        // Optional text is always placed by itself, so if it doesn't line up
+        // with a token we can just leave it out. Events land on the start of
+        // the last token, so a fused token like >> might only be half of the
+        // node; placing text after it would then be wrong.
+        events.retain(|event| {
+            !matches!(event.inner, Event::Optional(_) | Event::OptionalBefore(_))
+                || tokens.clone().any(|token| match token {
+                    RichToken::Token(inner) => {
+                        event.aligns_with(&inner) && !is_compound_punctuation(&inner.inner)
+                    }
+                    _ => false,
+                })
+        });
+        let mut events = events.as_slice();
 
         // Check for unaligned tokens. This can happen because of how syn parses
-        // ranges. In this case we just ignore expropen/close and just go by
-        // junk.
-        for event in events {
-            let mut found = false;
-            for token in tokens.clone() {
-                if let RichToken::Token(inner) = token {
-                    found |= event.aligns_with(&inner)
-                } else {
-                    continue;
-                }
-            }
-            if !found {
-                return self.populate_events(
-                    events
-                        .iter()
-                        .copied()
-                        .filter(|event| {
-                            matches!(event.inner, Event::StatementStart | Event::StatementEnd)
-                        })
-                        .collect::<Vec<_>>()
-                        .as_slice(),
-                );
-            }
+        // ranges. Events that don't line up are left out, and without them the
+        // other end of their parentheses or block can't be placed, so in this
+        // case we also ignore expropen/close and just go by junk.
+        let aligned = |event: &&Spanned<Event>| {
+            tokens
+                .clone()
+                .any(|token| matches!(token, RichToken::Token(inner) if event.aligns_with(&inner)))
+        };
+        if !events.iter().all(|event| aligned(&event)) {
+            let consts = events
+                .iter()
+                .filter(|event| matches!(event.inner, Event::ConstOpen | Event::ConstClose))
+                .all(|event| aligned(&event));
+            return self.populate_events(
+                events
+                    .iter()
+                    .filter(aligned)
+                    .copied()
+                    .filter(|event| match event.inner {
+                        Event::StatementStart
+                        | Event::StatementEnd
+                        | Event::ModuleItemStart(_)
+                        | Event::ModuleItemEnd(_)
+                        | Event::Attributable
+                        | Event::ItemStart { .. }
+                        | Event::Anchor
+                        | Event::Concat
+                        | Event::Literal
+                        | Event::Ident
+                        | Event::Spacer => true,
+                        Event::ConstOpen | Event::ConstClose => consts,
+                        _ => false,
+                    })
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            );
         }
 
         for token in tokens {
             match token {
                 RichToken::Junk(_)
+                | RichToken::ItemJunk { .. }
+                | RichToken::AttributeJunk(_)
                 | RichToken::Spacer
                 | RichToken::EndOfLineComment(_)
                 | RichToken::ExprOpen { .. }
-                | RichToken::ExprClose { .. } => out.push(token),
+                | RichToken::ExprClose { .. }
+                | RichToken::BlockOpen { .. }
+                | RichToken::BlockClose { .. }
+                | RichToken::Optional { .. }
+                | RichToken::Boundary { .. }
+                | RichToken::Anchor
+                | RichToken::ConstOpen
+                | RichToken::ConstClose
+                | RichToken::FrameSide
+                | RichToken::FrameEdge(_)
+                | RichToken::Text(_)
+                | RichToken::InlineComment(_)
+                | RichToken::CommentOpen(_)
+                | RichToken::CommentClose(_)
+                | RichToken::Concat
+                | RichToken::PadLiteral
+                | RichToken::PaddedLiteral { .. }
+                | RichToken::PadIdent
+                | RichToken::RawIdent(_)
+                | RichToken::Shorthand { .. }
+                | RichToken::Piece(_)
+                | RichToken::Segment { .. } => out.push(token),
                 RichToken::Token(inner) => {
                     let mut befores = vec![];
                     let mut afters = vec![];
@@ -185,17 +447,106 @@ impl<'a> Ir<'a> {
                             Event::StatementEnd => {
                                 afters.push(RichToken::Junk(0));
                             }
-                            Event::ExprOpen => {
+                            Event::ModuleItemStart(ty) => {
+                                let ty = ty.and_then(|ty| self.ident_at(ty));
+                                befores.push(RichToken::ItemJunk { len: 0, ty });
+                            }
+                            Event::Attributable => {
+                                befores.push(RichToken::AttributeJunk(0));
+                            }
+                            Event::ModuleItemEnd(ty) => {
+                                let ty = ty.and_then(|ty| self.ident_at(ty));
+                                afters.push(RichToken::ItemJunk { len: 0, ty });
+                            }
+                            event @ (Event::ExprOpen
+                            | Event::TypeOpen
+                            | Event::BoundOpen
+                            | Event::PatOpen
+                            | Event::UseOpen) => {
                                 let id = next_id;
-                                befores.push(RichToken::ExprOpen { id, reps: 0 });
+                                befores.push(RichToken::ExprOpen {
+                                    id,
+                                    reps: 0,
+                                    brace: matches!(event, Event::UseOpen),
+                                    once: matches!(event, Event::BoundOpen),
+                                });
                                 expr_starts.push(id);
                                 next_id += 1;
                             }
-                            Event::ExprClose => {
+                            event @ (Event::ExprClose
+                            | Event::TypeClose
+                            | Event::BoundClose
+                            | Event::PatClose
+                            | Event::UseClose) => {
                                 let id = expr_starts
                                     .pop()
                                     .expect("expression start was already added to stack");
-                                afters.push(RichToken::ExprClose { id, reps: 0 });
+                                afters.push(RichToken::ExprClose {
+                                    id,
+                                    reps: 0,
+                                    brace: matches!(event, Event::UseClose),
+                                });
+                            }
+                            Event::BlockOpen => {
+                                let id = next_id;
+                                befores.push(RichToken::BlockOpen { id, len: None });
+                                expr_starts.push(id);
+                                next_id += 1;
+                            }
+                            Event::BlockClose => {
+                                let id = expr_starts
+                                    .pop()
+                                    .expect("block start was already added to stack");
+                                afters.push(RichToken::BlockClose { id, on: false });
+                            }
+                            Event::Optional(choices) => {
+                                afters.push(RichToken::Optional {
+                                    choices,
+                                    chosen: None,
+                                });
+                            }
+                            Event::OptionalBefore(choices) => {
+                                befores.push(RichToken::Optional {
+                                    choices,
+                                    chosen: None,
+                                });
+                            }
+                            Event::ItemStart { pinned } => {
+                                befores.push(RichToken::Boundary { pinned });
+                            }
+                            Event::Anchor => {
+                                befores.push(RichToken::Anchor);
+                            }
+                            Event::ConstOpen => {
+                                befores.push(RichToken::ConstOpen);
+                            }
+                            Event::ConstClose => {
+                                afters.push(RichToken::ConstClose);
+                            }
+                            Event::Concat => {
+                                befores.push(RichToken::Concat);
+                            }
+                            Event::Literal => {
+                                befores.push(RichToken::PadLiteral);
+                            }
+                            Event::Ident => {
+                                befores.push(RichToken::PadIdent);
+                            }
+                            Event::Spacer => {
+                                let spaced = matches!(
+                                    befores.last().or(out.last()),
+                                    Some(RichToken::Spacer)
+                                );
+                                if !spaced {
+                                    befores.push(RichToken::Spacer);
+                                }
+                            }
+                            Event::Shorthand => {
+                                afters.push(RichToken::Shorthand {
+                                    ident: inner.inner.as_str(),
+                                    on: false,
+                                    reps: 0,
+                                });
                             }
                         }
                         events = &events[1..];
@@ -211,7 +562,117 @@ impl<'a> Ir<'a> {
         Ir { tokens: out }
     }
 
+    /// The identifier starting at `start`, if there is one.
+    fn ident_at(&self, start: TokenStart) -> Option<&'a str> {
+        self.tokens.iter().find_map(|token| match token {
+            RichToken::Token(Spanned {
+                inner: Token::Ident(ident),
+                region,
+            }) if *region == start => Some(*ident),
+            _ => None,
+        })
+    }
+
     pub fn tokens(&self) -> &[RichToken<'a>] {
         self.tokens.as_slice()
     }
 }
+
+impl<'a> FromIterator<RichToken<'a>> for Ir<'a> {
+    fn from_iter<T: IntoIterator<Item = RichToken<'a>>>(iter: T) -> Self {
+        Ir {
+            tokens: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// `literal` written `pad` characters longer without changing its value, if
+/// it can be. Numbers get leading zeros or digit separators, like `0_1` or
+/// `0x_ff`, and strings without escapes get more hashes as raw strings, like
+/// `r##"x"##`.
+pub fn pad_literal(literal: &str, pad: usize) -> Option<Cow<'_, str>> {
+    if pad == 0 {
+        return Some(Cow::Borrowed(literal));
+    }
+
+    if literal.starts_with(|c: char| c.is_ascii_digit()) {
+        let separators = "_".repeat(pad);
+        return match ["0x", "0o", "0b"]
+            .iter()
+            .find(|&&radix| literal.starts_with(radix))
+        {
+            Some(radix) => Some(Cow::Owned(format!("{radix}{separators}{}", &literal[2..]))),
+            None => Some(Cow::Owned(format!("0{}{literal}", &separators[1..]))),
+        };
+    }
+
+    let (prefix, rest) = literal.split_at(literal.find('"')?);
+    let kind = prefix.trim_end_matches('#');
+    if !["", "b", "c", "r", "br", "cr"].contains(&kind) || !literal.ends_with(['"', '#']) {
+        return None;
+    }
+    if kind.ends_with('r') {
+        // Hashes go on both ends
+        if pad % 2 != 0 {
+            return None;
+        }
+        let hashes = "#".repeat(pad / 2);
+        Some(Cow::Owned(format!(
+            "{kind}{hashes}{}{hashes}",
+            &literal[kind.len()..]
+        )))
+    } else {
+        // A raw string can't have escapes, and the r takes a character
+        if literal.contains('\\') || pad % 2 == 0 {
+            return None;
+        }
+        let hashes = "#".repeat(pad / 2);
+        Some(Cow::Owned(format!("{kind}r{hashes}{rest}{hashes}")))
+    }
+}
+
+/// Punctuation that types can end or start partway through.
+fn is_splittable(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::ShiftRight | Token::ShiftRightAssign | Token::GreaterThanEq | Token::BooleanAnd
+    )
+}
+
+/// A float that could be two tuple indices, such as `0.1` in `x.0.1`.
+fn is_tuple_indices(token: &Token) -> bool {
+    let Token::Literal(text) = token else {
+        return false;
+    };
+    text.split_once('.').is_some_and(|(first, second)| {
+        [first, second]
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// A decimal integer without a suffix, which a `.` after it would turn into a
+/// float.
+fn is_integer(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_digit())
+        && text.bytes().all(|b| b.is_ascii_digit() || b == b'_')
+}
+
+/// Whether `event` is on any character of `token`.
+fn lands_in(event: &Spanned<Event>, token: &Spanned<Token>) -> bool {
+    event.region.line == token.region.line
+        && (token.region.char..token.region.char + token.inner.as_str().len())
+            .contains(&event.region.char)
+}
+
+/// Punctuation made of several characters, such as `>>` or `..=`.
+fn is_compound_punctuation(token: &Token) -> bool {
+    !matches!(
+        token,
+        Token::Ident(_)
+            | Token::RawIdent(_)
+            | Token::Literal(_)
+            | Token::Lifetime(_)
+            | Token::MetaVar(_)
+    ) && token.as_str().len() > 1
+}
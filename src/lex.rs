@@ -82,28 +82,113 @@ pub fn lex_file(mut source: &str) -> anyhow::Result<Vec<Spanned<Token<'_>>>> {
     let mut tokens = vec![];
 
     while !source.is_empty() {
-        if let Some((token, rest)) = tokenizer.lex_textual_token(source) {
-            // Token is None if it lex_textual_token just stripped away whitespace
-            if let Some(token) = token {
-                tokens.push(token);
-            }
-            source = rest;
-        } else if let Some((token, rest)) = tokenizer.lex_punctuation_token(source) {
-            tokens.push(token); // Option<Token> implements iter
-            source = rest;
-        } else {
-            panic!("file should be valid rust syntax but could not detect next token")
+        let (token, rest) = tokenizer
+            .lex_token(source)
+            .expect("file should be valid rust syntax but could not detect next token");
+        // Token is None if whitespace or a comment was just stripped away
+        if let Some(token) = token {
+            tokens.push(token);
         }
+        source = rest;
     }
 
     Ok(tokens)
 }
 
+/// Whether `before` directly followed by `after` would be lexed as different
+/// tokens, such as `<` and `<` becoming `<<`, or `/` and `*` starting a
+/// comment. Text that can't be lexed counts as fusing.
+pub fn fuses(before: &str, after: &str) -> bool {
+    let texts = |mut source: &str| {
+        let mut lexer = Lexer::new();
+        let mut texts = vec![];
+        while !source.is_empty() {
+            let (token, rest) = lexer.lex_token(source)?;
+            texts.extend(token.map(|token| token.inner.as_str().to_string()));
+            source = rest;
+        }
+        Some(texts)
+    };
+    let apart = texts(before).zip(texts(after)).map(|(mut before, after)| {
+        before.extend(after);
+        before
+    });
+    apart.is_none() || texts(&format!("{before}{after}")) != apart
+}
+
+/// Split punctuation into one token per character, such as `>>` into `>` and
+/// `>`, where syn sees the characters as separate tokens.
+pub fn split_punctuation(token: Spanned<Token<'_>>) -> Vec<Spanned<Token<'_>>> {
+    let mut lexer = Lexer {
+        line: token.region.line,
+        char: token.region.char,
+    };
+    let text = token.inner.as_str();
+    (0..text.len())
+        .map(|i| {
+            let (token, _) = lexer
+                .lex_punctuation_token(&text[i..i + 1])
+                .expect("punctuation is made of single character tokens");
+            token
+        })
+        .collect()
+}
+
+/// Split tuple indices lexed as a float, such as `0.1` in `x.0.1`, into `0`
+/// and `.1`, so that `x.0` can end between them.
+pub fn split_float(token: Spanned<Token<'_>>) -> Vec<Spanned<Token<'_>>> {
+    let Token::Literal(text) = token.inner else {
+        return vec![token];
+    };
+    let Some(dot) = text.find('.') else {
+        return vec![token];
+    };
+    let TokenStart { line, char } = token.region;
+    vec![
+        Spanned::new(Token::Literal(&text[..dot]), line, char),
+        Spanned::new(Token::Literal(&text[dot..]), line, char + dot),
+    ]
+}
+
 impl Lexer {
     fn new() -> Self {
         Self { line: 1, char: 1 }
     }
 
+    /// Lexes the next token, which is `None` if it was whitespace or a
+    /// comment.
+    fn lex_token<'src>(
+        &mut self,
+        source: &'src str,
+    ) -> Option<(Option<Spanned<Token<'src>>>, &'src str)> {
+        if let Some(lexed) = self.lex_textual_token(source) {
+            return Some(lexed);
+        }
+        self.lex_metavariable(source)
+            .or_else(|| self.lex_punctuation_token(source))
+            .map(|(token, rest)| (Some(token), rest))
+    }
+
+    /// Lexes a metavariable in a macro, such as `$x` or `$crate`, as one
+    /// token so nothing gets put between the `$` and the name.
+    fn lex_metavariable<'src>(
+        &mut self,
+        source: &'src str,
+    ) -> Option<(Spanned<Token<'src>>, &'src str)> {
+        let name = source.strip_prefix('$').filter(|name| !name.is_empty())?;
+        let rustc_lexer::Token {
+            kind: TokenKind::Ident,
+            len,
+        } = rustc_lexer::first_token(name)
+        else {
+            return None;
+        };
+        let (text, rest) = source.split_at(len + 1);
+        let token = Spanned::new(Token::MetaVar(text), self.line, self.char);
+        self.char += text.safe_len();
+        Some((token, rest))
+    }
+
     /// Lexes simple tokens consisting of punction, such as operators.
     ///
     /// We have a custom function for doing this because rustc_lexer is extremely
@@ -235,6 +320,8 @@ pub enum Token<'a> {
     Lifetime(&'a str),
     Literal(&'a str),
     RawIdent(&'a str),
+    /// A metavariable in a macro, like `$x`
+    MetaVar(&'a str),
 
     // Complex Puncation Tokens
     RangeInclusive,
@@ -307,6 +394,7 @@ impl<'a> Token<'a> {
             Token::Lifetime(s) => s,
             Token::Literal(s) => s,
             Token::RawIdent(s) => s,
+            Token::MetaVar(s) => s,
             Token::RangeInclusive => "..=",
             Token::VariadicArgs => "...",
             Token::Range => "..",
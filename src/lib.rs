@@ -1,3 +1,5 @@
+use std::{borrow::Cow, ops::RangeInclusive};
+
 use anyhow::Context;
 use ir::Ir;
 use location::Visitor;
@@ -8,13 +10,16 @@ mod location;
 mod emit;
 mod ir;
 mod lex;
+mod regroup;
+mod reorder;
+mod split;
 
 const JUNK: [&str; 81] = [
     "",
     ";",
     "3;",
     "();",
-    "{;};",
+    "{3};",
     "({});",
     "{();};",
     "*&*&();",
@@ -93,36 +98,422 @@ const JUNK: [&str; 81] = [
     "if true{};if true{};if true{};if true{};if true{};if true{};if true{};if true{};",
 ];
 
+/// Junk statements exactly `len` characters long.
+///
+/// Padding with `;;` was dropped on purpose: an empty statement trips rustc's
+/// `redundant_semicolons` lint, so the table has `{3};` rather than `{;};`, and
+/// lengths whose junk would still have one are skipped, see
+/// [`has_empty_statement`]. Trailing commas and `+` take the odd leftovers
+/// instead.
+fn junk(len: usize) -> Cow<'static, str> {
+    let longest = JUNK.len() - 1;
+    if len <= longest {
+        Cow::Borrowed(JUNK[len])
+    } else {
+        Cow::Owned(JUNK[longest].repeat(len / longest) + JUNK[len % longest])
+    }
+}
+
+/// Whether [`junk`] of this length has an empty statement, which rustc warns
+/// about. A lone `;` can't be anything else.
+fn has_empty_statement(len: usize) -> bool {
+    let junk = junk(len);
+    junk.starts_with(';') || junk.contains(";;")
+}
+
+/// Number of statements in [`junk`] of this length.
+fn junk_statements(len: usize) -> usize {
+    let mut depth = 0;
+    let mut statements = 0;
+    for c in junk(len).chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ';' if depth == 0 => statements += 1,
+            _ => {}
+        }
+    }
+    statements
+}
+
+/// Longest [`junk`] that, along with all shorter junk, has at most this many
+/// statements.
+fn junk_capacity(statements: usize) -> usize {
+    (1..)
+        .find(|&len| junk_statements(len) > statements)
+        .expect("junk gets more statements as it gets longer")
+        - 1
+}
+
+/// Junk items of each length from 13, the shortest, to 25. Longer item junk
+/// joins two of these.
+const ITEM_JUNK: [&str; 13] = [
+    "const _:u8=0;",
+    "const _:()=();",
+    "const _:u8=1+1;",
+    "const _:&str=\"\";",
+    "const _:()={();};",
+    "const _:bool=true;",
+    "const _:bool=false;",
+    "const _:[();1]=[()];",
+    "const _:()=if true{};",
+    "const _:(u8,u8)=(0,0);",
+    "const _:()=loop{break};",
+    "const _:()=loop{break;};",
+    "const _:usize=usize::MAX;",
+];
+
+/// Junk items exactly `len` characters long, if there are any. Lengths too
+/// short for a constant can be made of empty `impl` blocks for `ty`, a type in
+/// the same module.
+fn item_junk(len: usize, ty: Option<&str>) -> Option<Cow<'static, str>> {
+    let shortest = ITEM_JUNK[0].len();
+    let longest = shortest + ITEM_JUNK.len() - 1;
+    match len {
+        0 => Some(Cow::Borrowed("")),
+        _ if len < shortest => {
+            let block = format!("impl {}{{}}", ty?);
+            let block_len = block.as_str().safe_len();
+            (len % block_len == 0).then(|| Cow::Owned(block.repeat(len / block_len)))
+        }
+        _ if len <= longest => Some(Cow::Borrowed(ITEM_JUNK[len - shortest])),
+        _ => {
+            let half = len / 2;
+            let first = item_junk(half, None)?;
+            let second = item_junk(len - half, None)?;
+            Some(Cow::Owned(first.into_owned() + &second))
+        }
+    }
+}
+
+/// No-op attributes of each length from 13, the shortest, to 25, except 15 to
+/// 17. Longer attribute junk joins two of these. They're all `cfg`s that hold
+/// and `cfg_attr`s that don't, since `allow` is an error under `forbid`.
+const ATTRIBUTE_JUNK: [Option<&str>; 13] = [
+    Some("#[cfg(all())]"),
+    Some("#[cfg(all(),)]"),
+    None,
+    None,
+    None,
+    Some("#[cfg(not(any()))]"),
+    Some("#[cfg(all(all(),))]"),
+    Some("#[cfg_attr(any(),x)]"),
+    Some("#[cfg_attr(any(),x,)]"),
+    Some("#[cfg_attr(any(),x,y)]"),
+    Some("#[cfg(all(not(any())))]"),
+    Some("#[cfg(all(not(any()),))]"),
+    Some("#[cfg_attr(not(all()),x)]"),
+];
+
+/// No-op attributes exactly `len` characters long, if there are any.
+fn attribute_junk(len: usize) -> Option<Cow<'static, str>> {
+    let shortest = ATTRIBUTE_JUNK[0]?.len();
+    let longest = shortest + ATTRIBUTE_JUNK.len() - 1;
+    match len {
+        0 => Some(Cow::Borrowed("")),
+        _ if len < shortest => None,
+        _ if len <= longest => ATTRIBUTE_JUNK[len - shortest].map(Cow::Borrowed),
+        // Split as evenly as possible around the missing lengths
+        _ => (shortest..=len / 2).rev().find_map(|half| {
+            let first = attribute_junk(half)?;
+            let second = attribute_junk(len - half)?;
+            Some(Cow::Owned(first.into_owned() + &second))
+        }),
+    }
+}
+
+/// Options controlling the shape of unformatted code.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Length of every line.
+    pub width: usize,
+    /// Give each top-level item its own rectangle, separated by blank lines.
+    pub item_rectangles: bool,
+    /// Anchor line breaks to item and statement boundaries, so that small
+    /// changes to the source only change the output around them.
+    pub stable: bool,
+    /// Exact number of lines to fill with code and junk.
+    pub height: Option<usize>,
+    /// Wrap each rectangle in a border of comments, which counts towards the
+    /// width and height.
+    pub frame: bool,
+    /// Text spelled out by the first visible character of consecutive lines.
+    /// Whitespace is skipped.
+    pub acrostic: Option<String>,
+    /// Most extra pairs of parentheses around any one expression.
+    pub max_parens: Option<usize>,
+    /// Most junk statements, or junk items, in a row.
+    pub max_junk: Option<usize>,
+    /// Take turns adding junk and parentheses across a line, instead of
+    /// adding junk first.
+    pub spread: bool,
+    /// What to do with tokens longer than the width.
+    pub overflow: Overflow,
+    /// Also try reordering top-level items to pack lines better. Macros and
+    /// modules that export them stay where they are.
+    pub reorder: bool,
+    /// Also try splitting and merging `use` declarations to pack lines better.
+    pub regroup: bool,
+}
+
+/// What to do with tokens too long to fit on a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Let them stick out of the rectangle.
+    #[default]
+    Allow,
+    /// Split string literals across lines. Other tokens stick out.
+    Split,
+    /// Return an error.
+    Error,
+    /// Widen the rectangle to fit the widest token.
+    Widen,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            item_rectangles: false,
+            stable: false,
+            height: None,
+            frame: false,
+            acrostic: None,
+            max_parens: None,
+            max_junk: None,
+            spread: false,
+            overflow: Overflow::Allow,
+            reorder: false,
+            regroup: false,
+        }
+    }
+}
+
 /// Unformat a source file into lines of length `width`.
 ///
 /// ## Details
 /// This process strips comments, inserts no-op statements, and wraps expressions
-/// in extra parentheses to achieve the desired line length.
+/// in extra parentheses to achieve the desired line length. Regrouping `use`
+/// declarations and reordering items are opt-in, through [`unformat_with`] and
+/// [`Config::regroup`] or [`Config::reorder`].
 ///
 /// ## Errors
 /// Returns an error if the source file is not valid Rust.
 ///
 /// This function returns a spurious error if source has documentation comments
 /// not at the start of a line, for example:
-/// ```
+/// ```ignore
 /// let x = blah; /// bad!
 /// ```
 /// This is because we use syn under the hood, which does not understand doc comments.
 pub fn unformat(src: &str, width: usize) -> anyhow::Result<Vec<u8>> {
+    unformat_with(
+        src,
+        &Config {
+            width,
+            ..Config::default()
+        },
+    )
+}
+
+/// Unformat a source file according to `config`.
+///
+/// ## Errors
+/// See [`unformat`]. Also returns an error if the code doesn't fit in
+/// `config.height` lines, or if the options can't be combined.
+pub fn unformat_with(src: &str, config: &Config) -> anyhow::Result<Vec<u8>> {
+    check_config(config)?;
+
     let src = remove_doc_comments(src);
 
-    let tokens = lex::lex_file(&src).context("source was not valid")?;
+    // Keep whichever grouping of imports and order of items packs best
+    let mut best: Option<(f64, Vec<u8>)> = None;
+    let mut error = None;
+    for src in groupings(&src, config) {
+        let ir = build_ir(&src)?;
+        let packed = config.reorder.then(|| reorder::pack(&ir, config)).flatten();
+        for ir in std::iter::once(&ir).chain(&packed) {
+            let mut unformatted = vec![];
+            match crate::emit::unformat(&mut unformatted, ir, config) {
+                Ok(stats) => {
+                    let score = Objective::Comments.score(&stats);
+                    if best.as_ref().map_or(true, |(best, _)| score < *best) {
+                        best = Some((score, unformatted));
+                    }
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+    }
 
-    let mut stmts = Visitor::new();
-    stmts.visit_file(&syn::parse_file(&src).unwrap());
+    match best {
+        Some((_, unformatted)) => Ok(unformatted),
+        None => Err(error.expect("there is always a grouping as written")),
+    }
+}
 
-    let ir = Ir::new(tokens.into_iter());
-    let ir = ir.populate_events(stmts.events());
+/// Unformat a source file at every width in `widths`, keeping the layout that
+/// scores best on `objective`. `config.width` is ignored.
+///
+/// ## Errors
+/// See [`unformat_with`]. Widths where the code doesn't fit in `config.height`
+/// lines are skipped, and an error is returned if no width fits.
+pub fn search_width(
+    src: &str,
+    config: &Config,
+    widths: RangeInclusive<usize>,
+    objective: Objective,
+) -> anyhow::Result<Search> {
+    check_config(config)?;
+
+    let src = remove_doc_comments(src);
+    let sources = groupings(&src, config);
+    let irs = sources
+        .iter()
+        .map(|src| build_ir(src))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut best: Option<(f64, usize, Vec<u8>)> = None;
+    let mut scores = vec![];
+    for width in widths.clone() {
+        let config = Config {
+            width,
+            ..config.clone()
+        };
+
+        // Keep whichever grouping of imports and order of items packs best at
+        // this width
+        let mut best_here: Option<(f64, Vec<u8>)> = None;
+        for ir in &irs {
+            let packed = config.reorder.then(|| reorder::pack(ir, &config)).flatten();
+            for ir in std::iter::once(ir).chain(&packed) {
+                let mut unformatted = vec![];
+                let Ok(stats) = crate::emit::unformat(&mut unformatted, ir, &config) else {
+                    continue;
+                };
+                let score = objective.score(&stats);
+                if best_here.as_ref().map_or(true, |(best, _)| score < *best) {
+                    best_here = Some((score, unformatted));
+                }
+            }
+        }
+        let Some((score, unformatted)) = best_here else {
+            continue;
+        };
+
+        scores.push((width, score));
+        if best.as_ref().map_or(true, |(best, ..)| score < *best) {
+            best = Some((score, width, unformatted));
+        }
+    }
+
+    let Some((_, width, unformatted)) = best else {
+        anyhow::bail!("no width from {} to {} fits", widths.start(), widths.end());
+    };
+
+    Ok(Search {
+        width,
+        unformatted,
+        scores,
+    })
+}
 
-    let mut unformatted = vec![];
-    crate::emit::block(&mut unformatted, &ir, width);
+/// What makes a layout look good when searching for a width. Lower scores are
+/// better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    /// Fewest end of line comments. Ties are broken by [`Objective::Junk`].
+    Comments,
+    /// Smallest fraction of characters that aren't code.
+    Junk,
+    /// Closest to this many columns per line. Characters are about twice as
+    /// tall as they are wide, so a ratio of 2 looks square.
+    Aspect(f64),
+}
+
+impl Objective {
+    pub const SQUARE: Objective = Objective::Aspect(2.0);
+
+    /// Score a layout, lower is better.
+    pub fn score(&self, stats: &Stats) -> f64 {
+        let area = (stats.width * stats.lines).max(1) as f64;
+        match self {
+            Objective::Comments => stats.comments as f64 + stats.filler as f64 / area,
+            Objective::Junk => stats.filler as f64 / area,
+            Objective::Aspect(ratio) => {
+                let actual = stats.width as f64 / stats.lines.max(1) as f64;
+                (actual / ratio).ln().abs()
+            }
+        }
+    }
+}
+
+/// Measurements of an unformatted layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Length of every line.
+    pub width: usize,
+    /// Number of lines, including lines inside multi-line literals.
+    pub lines: usize,
+    /// Number of comments filling out lines.
+    pub comments: usize,
+    /// Characters that aren't code: junk, parentheses, comments, and spaces.
+    pub filler: usize,
+}
+
+/// The result of [`search_width`].
+#[derive(Debug, Clone)]
+pub struct Search {
+    /// Width with the best score.
+    pub width: usize,
+    /// Source unformatted at `width`.
+    pub unformatted: Vec<u8>,
+    /// Score of every width that fit, in order of width.
+    pub scores: Vec<(usize, f64)>,
+}
 
-    Ok(unformatted)
+fn check_config(config: &Config) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        config.height.is_none() || !config.item_rectangles,
+        "a height can't be combined with per-item rectangles"
+    );
+    anyhow::ensure!(
+        config.acrostic.is_none()
+            || !(config.item_rectangles || config.stable || config.frame || config.height.is_some()),
+        "an acrostic can't be combined with per-item rectangles, stable layout, a frame, or a height"
+    );
+    // Nothing can come after a backslash that continues a string
+    anyhow::ensure!(
+        config.overflow != Overflow::Split || !(config.frame || config.acrostic.is_some()),
+        "splitting strings can't be combined with a frame or an acrostic"
+    );
+    // Moving items or imports moves every line break after them
+    anyhow::ensure!(
+        !config.stable || !(config.reorder || config.regroup),
+        "stable layout can't be combined with reordering items or regrouping imports"
+    );
+    Ok(())
+}
+
+/// `src`, then with its imports regrouped if `config` asks for that.
+fn groupings<'a>(src: &'a str, config: &Config) -> Vec<Cow<'a, str>> {
+    match config.regroup {
+        true => regroup::groupings(src),
+        false => vec![Cow::Borrowed(src)],
+    }
+}
+
+/// Lex, parse and annotate source with doc comments already removed.
+fn build_ir(src: &str) -> anyhow::Result<Ir<'_>> {
+    let tokens = lex::lex_file(src).context("source was not valid")?;
+
+    let mut stmts = Visitor::new();
+    stmts.visit_file(&syn::parse_file(src).unwrap());
+
+    let ir = Ir::new(tokens.into_iter());
+    Ok(ir.populate_events(stmts.events()))
 }
 
 /// Remove doc comments heuristically. Necessary because syn doesn't understand them
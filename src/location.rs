@@ -1,36 +1,501 @@
+//! Finding the places in a file where it can be padded without changing what
+//! it means.
+//!
+//! Extra parentheses are allowed wherever the grammar allows them, around
+//! expressions, types and patterns alike. rustc's `unused_parens` lint fires on
+//! most of them, and that's accepted: it can't tell padding from any other
+//! parentheses, and there's nothing else to pad most of a line with. Junk is
+//! different, since there's always junk of the same length that doesn't trip
+//! a lint, so empty statements and braces around nothing but a value are
+//! avoided.
+
+use std::collections::{HashMap, HashSet};
+
 use crate::lex::{self, TokenStart};
 
-use proc_macro2::TokenTree;
+use proc_macro2::{Delimiter, Spacing, Span, TokenStream, TokenTree};
 use quote::ToTokens;
 use syn::{
+    parse::ParseStream,
+    punctuated::Punctuated,
     visit::{self, Visit},
-    Stmt, StmtMacro,
+    Expr, GenericArgument, Pat, ReturnType, Signature, Stmt, StmtMacro, Type, TypeParamBound, UnOp,
+    UseTree, Visibility,
 };
 
 #[derive(Debug, Default)]
 pub struct Visitor {
     events: Vec<lex::Spanned<Event>>,
+    in_attribute: bool,
+    /// The next expression visited can't be wrapped in parentheses
+    bare: bool,
+    /// Names of macros defined in the file, which may not be the ones from
+    /// the standard library
+    macros: HashSet<String>,
+    /// Inside the transcriber of a `macro_rules!`, which was already spaced
+    in_macro_rules: bool,
+    /// Metavariables in transcribers, which are parsed as identifiers but
+    /// aren't ones
+    metavars: Vec<TokenStart>,
+    /// Names of constants and statics defined in the file, which patterns
+    /// match against instead of binding
+    consts: HashSet<String>,
+    /// Inside a function without a body, whose parameters can't be patterns
+    in_declaration: bool,
+    /// Inside a pattern, where an or-pattern can be wrapped in parentheses
+    in_pattern: bool,
 }
 
 impl Visitor {
     pub fn new() -> Self {
-        Self { events: vec![] }
+        Self {
+            events: vec![],
+            in_attribute: false,
+            bare: false,
+            macros: HashSet::new(),
+            in_macro_rules: false,
+            metavars: vec![],
+            consts: HashSet::new(),
+            in_declaration: false,
+            in_pattern: false,
+        }
     }
 
     pub fn events(&self) -> &[lex::Spanned<Event>] {
         self.events.as_slice()
     }
+
+    /// Allow an extra separator after the last element of `list` if it doesn't
+    /// already have one.
+    fn optional_separator<T: ToTokens, P>(
+        &mut self,
+        list: &Punctuated<T, P>,
+        choices: &'static [&'static str],
+    ) {
+        if list.trailing_punct() {
+            return;
+        }
+        if let Some(last) = list.last() {
+            self.optional_after(last, choices);
+        }
+    }
+
+    /// Mark the start of `node` as a place where a line break may be anchored.
+    fn anchor(&mut self, node: &impl ToTokens) {
+        let TokenStart { line, char } = first_token_start(node);
+        self.events
+            .push(lex::Spanned::new(Event::Anchor, line, char));
+    }
+
+    /// Visit an item directly inside a module, allowing junk items around it.
+    /// Junk can't go between items in impls, traits or extern blocks.
+    fn module_item(&mut self, item: &syn::Item, ty: Option<TokenStart>) {
+        let TokenStart { line, char } = first_token_start(item);
+        self.events
+            .push(lex::Spanned::new(Event::ModuleItemStart(ty), line, char));
+        self.visit_item(item);
+        let TokenStart { line, char } = last_token_start(item);
+        self.events
+            .push(lex::Spanned::new(Event::ModuleItemEnd(ty), line, char));
+    }
+
+    /// Allow `ty` to be wrapped in parentheses.
+    fn wrap_type(&mut self, ty: &Type) {
+        let TokenStart { line, char } = first_token_start(ty);
+        self.events
+            .push(lex::Spanned::new(Event::TypeOpen, line, char));
+        visit::visit_type(self, ty);
+        let TokenStart { line, char } = last_token_start(ty);
+        self.events
+            .push(lex::Spanned::new(Event::TypeClose, line, char));
+    }
+
+    /// Visit `bounds`, allowing each trait bound to be wrapped in parentheses.
+    /// The bounds of `impl Trait` and `dyn Trait` are left alone.
+    fn wrap_bounds<P>(&mut self, bounds: &Punctuated<TypeParamBound, P>) {
+        for bound in bounds {
+            let TypeParamBound::Trait(trait_bound) = bound else {
+                self.visit_type_param_bound(bound);
+                continue;
+            };
+            // A bound can only be wrapped once
+            if trait_bound.paren_token.is_some() {
+                self.visit_trait_bound(trait_bound);
+                continue;
+            }
+            let TokenStart { line, char } = first_token_start(bound);
+            self.events
+                .push(lex::Spanned::new(Event::BoundOpen, line, char));
+            self.visit_trait_bound(trait_bound);
+            let TokenStart { line, char } = last_token_start(bound);
+            self.events
+                .push(lex::Spanned::new(Event::BoundClose, line, char));
+        }
+    }
+
+    /// Allow `pat` to be wrapped in parentheses. The ends of a range are
+    /// patterns too, not expressions, so they're left alone.
+    fn wrap_pat(&mut self, pat: &Pat) {
+        let TokenStart { line, char } = first_token_start(pat);
+        self.events
+            .push(lex::Spanned::new(Event::PatOpen, line, char));
+        match pat {
+            Pat::Range(_) => {}
+            // x can become x@_, unless it's a constant and not a binding
+            Pat::Ident(ident) if ident.subpat.is_none() && self.is_binding(&ident.ident) => {
+                visit::visit_pat(self, pat);
+                self.optional_after(&ident.ident, &["@_"]);
+            }
+            _ => visit::visit_pat(self, pat),
+        }
+        let TokenStart { line, char } = last_token_start(pat);
+        self.events
+            .push(lex::Spanned::new(Event::PatClose, line, char));
+    }
+
+    /// Allow `tree` to be wrapped in braces.
+    fn wrap_use_tree(&mut self, tree: &UseTree) {
+        let TokenStart { line, char } = first_token_start(tree);
+        self.events
+            .push(lex::Spanned::new(Event::UseOpen, line, char));
+        self.visit_use_tree(tree);
+        let TokenStart { line, char } = last_token_start(tree);
+        self.events
+            .push(lex::Spanned::new(Event::UseClose, line, char));
+    }
+
+    /// Whether a pattern that's just `ident` binds a new variable, as far as
+    /// can be told from this file. Constants matched against are usually
+    /// uppercase, but not always.
+    fn is_binding(&self, ident: &proc_macro2::Ident) -> bool {
+        let name = ident.to_string();
+        !self.in_declaration
+            && !self.metavars.contains(&first_token_start(ident))
+            && name.starts_with(|c: char| c.is_lowercase() || c == '_')
+            && !self.consts.contains(&name)
+    }
+
+    /// Allow the shorthand field `member` to be spelled out as `x: x`.
+    fn shorthand(&mut self, member: &syn::Member) {
+        if let syn::Member::Named(ident) = member {
+            let TokenStart { line, char } = first_token_start(ident);
+            self.events
+                .push(lex::Spanned::new(Event::Shorthand, line, char));
+        }
+    }
+
+    /// Visit the left side of an assignment. Tuples, arrays and structs there
+    /// destructure like patterns, so only the places inside them are visited,
+    /// and those can't be wrapped either, since `(..)` and `(_)` aren't
+    /// patterns.
+    fn visit_assignee(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Tuple(tuple) => {
+                for elem in &tuple.elems {
+                    self.visit_assignee(elem);
+                }
+            }
+            Expr::Array(array) => {
+                for elem in &array.elems {
+                    self.visit_assignee(elem);
+                }
+            }
+            Expr::Call(call) => {
+                self.visit_bare_expr(&call.func);
+                for arg in &call.args {
+                    self.visit_assignee(arg);
+                }
+            }
+            Expr::Struct(expr) => {
+                self.visit_path(&expr.path);
+                for field in &expr.fields {
+                    for attr in &field.attrs {
+                        self.visit_attribute(attr);
+                    }
+                    if field.colon_token.is_some() {
+                        self.visit_member(&field.member);
+                    }
+                    self.visit_assignee(&field.expr);
+                }
+            }
+            Expr::Paren(paren) => self.visit_assignee(&paren.expr),
+            Expr::Range(_) | Expr::Infer(_) => {}
+            _ => self.visit_bare_expr(expr),
+        }
+    }
+
+    /// Visit `expr` where it can't be wrapped in parentheses, though its parts
+    /// can.
+    fn visit_bare_expr(&mut self, expr: &Expr) {
+        self.bare = true;
+        self.visit_expr(expr);
+    }
+
+    /// Visit the length of an array, which can be a const generic parameter
+    /// but not one in parentheses.
+    fn visit_array_len(&mut self, len: &Expr) {
+        self.visit_const(len, |this| {
+            if let Expr::Path(_) = len {
+                this.bare = true;
+            }
+            this.visit_expr(len);
+        });
+    }
+
+    /// Visit `node`, which is evaluated at compile time, with `visit`. Only
+    /// some junk is `const`.
+    fn visit_const(&mut self, node: &impl ToTokens, visit: impl FnOnce(&mut Self)) {
+        let TokenStart { line, char } = first_token_start(node);
+        self.events
+            .push(lex::Spanned::new(Event::ConstOpen, line, char));
+        visit(self);
+        let TokenStart { line, char } = last_token_start(node);
+        self.events
+            .push(lex::Spanned::new(Event::ConstClose, line, char));
+    }
+
+    /// Allow `expr`, which is used as a value, to be wrapped in a block that
+    /// junk statements can go in.
+    fn wrap_block(&mut self, expr: &Expr) {
+        if self.in_attribute || !is_blockable(expr) {
+            self.visit_expr(expr);
+            return;
+        }
+        let TokenStart { line, char } = first_token_start(expr);
+        self.events
+            .push(lex::Spanned::new(Event::BlockOpen, line, char));
+        self.visit_expr(expr);
+        let TokenStart { line, char } = last_token_start(expr);
+        self.events
+            .push(lex::Spanned::new(Event::BlockClose, line, char));
+    }
+
+    /// Allow attributes to be added to `node`, after the ones it has. Doc
+    /// comments are attributes to syn, but not tokens to the lexer.
+    fn attributable(&mut self, node: &impl ToTokens) {
+        let tokens = node.to_token_stream().into_iter().collect::<Vec<_>>();
+        let mut rest = tokens.as_slice();
+        while let [TokenTree::Punct(pound), TokenTree::Group(group), after @ ..] = rest {
+            if pound.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+                break;
+            }
+            rest = after;
+        }
+        if let Some(first) = rest.first() {
+            let TokenStart { line, char } = TokenStart::from(span_start(first));
+            self.events
+                .push(lex::Spanned::new(Event::Attributable, line, char));
+        }
+    }
+
+    /// Allow one of `choices` to be inserted directly after `node`.
+    fn optional_after(&mut self, node: &impl ToTokens, choices: &'static [&'static str]) {
+        let TokenStart { line, char } = last_token_start(node);
+        self.events
+            .push(lex::Spanned::new(Event::Optional(choices), line, char));
+    }
+
+    /// Allow one of `choices` to be inserted directly before `node`.
+    fn optional_before(&mut self, node: &impl ToTokens, choices: &'static [&'static str]) {
+        let TokenStart { line, char } = first_token_start(node);
+        self.events.push(lex::Spanned::new(
+            Event::OptionalBefore(choices),
+            line,
+            char,
+        ));
+    }
+
+    /// Allow a function without a visibility qualifier to spell out the
+    /// default one.
+    fn optional_visibility(&mut self, vis: &Visibility, sig: &Signature) {
+        if let Visibility::Inherited = vis {
+            self.optional_before(sig, &["pub(self)", "pub(in self)"]);
+        }
+    }
+
+    /// Keep apart the tokens of a macro that were apart in the source and
+    /// would be lexed differently together. Macros match tokens exactly, so
+    /// `$($x:ident)+ =` can't become `+=`, and `< <` isn't `<<`.
+    fn space_macro(&mut self, tokens: &TokenStream) {
+        let mut units = vec![];
+        lexer_tokens(tokens.clone(), &mut units);
+        for pair in units.windows(2) {
+            let [(before, _, end), (after, start, _)] = pair else {
+                unreachable!("windows are pairs");
+            };
+            if end.end() != start.start() && lex::fuses(before, after) {
+                let TokenStart { line, char } = TokenStart::from(*start);
+                self.events
+                    .push(lex::Spanned::new(Event::Spacer, line, char));
+            }
+        }
+    }
+
+    /// Pad the transcribers of a `macro_rules!` that expand to a value, where
+    /// every metavariable is a fragment that parses as one piece, like an
+    /// `expr` or an `ident`. Matchers are never padded.
+    fn visit_macro_rules(&mut self, tokens: &TokenStream) {
+        let trees = tokens.clone().into_iter().collect::<Vec<_>>();
+        let rules =
+            trees.split(|tree| matches!(tree, TokenTree::Punct(punct) if punct.as_char() == ';'));
+        for rule in rules {
+            // A matcher, =>, and a transcriber
+            let [TokenTree::Group(matcher), _, _, TokenTree::Group(transcriber)] = rule else {
+                continue;
+            };
+            let mut fragments = HashMap::new();
+            collect_fragments(matcher.stream(), &mut fragments);
+            let mut metavars = vec![];
+            let Some(stream) = substitute(transcriber.stream(), &fragments, &mut metavars) else {
+                continue;
+            };
+            let Ok(expr) = syn::parse2::<Expr>(stream) else {
+                continue;
+            };
+            if !is_value(&expr) {
+                continue;
+            }
+
+            self.metavars.extend(metavars);
+            let outer = std::mem::replace(&mut self.in_macro_rules, true);
+            // The expansion isn't wrapped as a whole, since it might be a
+            // statement that needs to stay block-like
+            self.visit_bare_expr(&expr);
+            self.in_macro_rules = outer;
+        }
+    }
+
+    /// Allow an empty generic list, unit return type and where clause to be
+    /// added to a function signature.
+    fn pad_signature(&mut self, sig: &Signature) {
+        if sig.generics.lt_token.is_none() {
+            self.optional_after(&sig.ident, &["<>"]);
+        }
+        let TokenStart { line, char } = TokenStart::from(sig.paren_token.span.close());
+        if let ReturnType::Default = sig.output {
+            self.events
+                .push(lex::Spanned::new(Event::Optional(&["->()"]), line, char));
+        }
+        if sig.generics.where_clause.is_none() {
+            match &sig.output {
+                ReturnType::Default => {
+                    self.events
+                        .push(lex::Spanned::new(Event::Optional(&["where"]), line, char))
+                }
+                // where would fuse with a type ending in a name
+                ReturnType::Type(_, ty) => match ty.to_token_stream().into_iter().last() {
+                    Some(TokenTree::Ident(_)) => self.optional_after(ty, &[" where"]),
+                    _ => self.optional_after(ty, &["where"]),
+                },
+            }
+        }
+    }
 }
 
 impl Visit<'_> for Visitor {
+    fn visit_file(&mut self, i: &'_ syn::File) {
+        let mut macros = Macros::default();
+        macros.visit_file(i);
+        self.macros = macros.0;
+        let mut consts = Consts::default();
+        consts.visit_file(i);
+        self.consts = consts.0;
+
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        let ty = local_type(&i.items);
+        for item in &i.items {
+            let TokenStart { line, char } = first_token_start(item);
+            self.events.push(lex::Spanned::new(
+                Event::ItemStart {
+                    pinned: is_pinned(item),
+                },
+                line,
+                char,
+            ));
+            self.module_item(item, ty);
+        }
+    }
+
+    fn visit_item_mod(&mut self, i: &'_ syn::ItemMod) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_visibility(&i.vis);
+        if let Some((_, items)) = &i.content {
+            let ty = local_type(items);
+            for item in items {
+                self.module_item(item, ty);
+            }
+        }
+    }
+
+    fn visit_block(&mut self, i: &'_ syn::Block) {
+        // Statements without a semicolon that aren't the tail of the block are
+        // block-like expressions such as `if x {}`. Their type must be `()`,
+        // so junk can safely follow them.
+        if let Some((_, init)) = i.stmts.split_last() {
+            for stmt in init {
+                if let Stmt::Expr(_, None)
+                | Stmt::Macro(StmtMacro {
+                    semi_token: None, ..
+                }) = stmt
+                {
+                    let (start, end) = stmt_endpoints(stmt);
+                    self.anchor(stmt);
+                    self.events.push(start);
+                    // `(if x {}) y` would need a semicolon in between
+                    match stmt {
+                        Stmt::Expr(expr, _) => self.visit_bare_expr(expr),
+                        _ => visit::visit_stmt(self, stmt),
+                    }
+                    self.events.push(end);
+                } else {
+                    self.visit_stmt(stmt);
+                }
+            }
+        }
+        if let Some(tail) = i.stmts.last() {
+            self.visit_stmt(tail);
+        } else {
+            // Empty blocks are always (), and so is junk
+            let TokenStart { line, char } = TokenStart::from(i.brace_token.span.close());
+            self.events
+                .push(lex::Spanned::new(Event::StatementStart, line, char));
+        }
+    }
+
+    fn visit_item(&mut self, i: &'_ syn::Item) {
+        self.anchor(i);
+        visit::visit_item(self, i);
+    }
+
+    fn visit_impl_item(&mut self, i: &'_ syn::ImplItem) {
+        self.anchor(i);
+        visit::visit_impl_item(self, i);
+    }
+
+    fn visit_trait_item(&mut self, i: &'_ syn::TraitItem) {
+        self.anchor(i);
+        visit::visit_trait_item(self, i);
+    }
+
     fn visit_stmt(&mut self, i: &'_ syn::Stmt) {
+        // Items anchor themselves
+        if !matches!(i, Stmt::Item(_)) {
+            self.anchor(i);
+        }
+
         if let Stmt::Expr(_, None)
         | Stmt::Macro(StmtMacro {
             semi_token: None, ..
         }) = i
         {
-            // These statements don't have a semicolon so we can't put junk after them.
-            // Keep recursing using default visitor
+            // These statements don't have a semicolon so we can't put junk after them,
+            // only before. Keep recursing using default visitor
+            let (start, _) = stmt_endpoints(i);
+            self.events.push(start);
             visit::visit_stmt(self, i);
         } else {
             // Output statement start/begins in DFS order
@@ -42,36 +507,604 @@ impl Visit<'_> for Visitor {
         }
     }
 
+    fn visit_expr_array(&mut self, i: &'_ syn::ExprArray) {
+        visit::visit_expr_array(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_expr_call(&mut self, i: &'_ syn::ExprCall) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_expr(&i.func);
+        for arg in &i.args {
+            self.wrap_block(arg);
+        }
+        self.optional_separator(&i.args, &[","]);
+    }
+
+    fn visit_expr_method_call(&mut self, i: &'_ syn::ExprMethodCall) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_expr(&i.receiver);
+        if let Some(turbofish) = &i.turbofish {
+            self.visit_angle_bracketed_generic_arguments(turbofish);
+        }
+        for arg in &i.args {
+            self.wrap_block(arg);
+        }
+        self.optional_separator(&i.args, &[","]);
+    }
+
+    fn visit_expr_tuple(&mut self, i: &'_ syn::ExprTuple) {
+        // Tuples with one element always have a trailing comma, so this never
+        // turns a parenthesized expression into a tuple
+        visit::visit_expr_tuple(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_local_init(&mut self, i: &'_ syn::LocalInit) {
+        self.visit_expr(&i.expr);
+        // let-else needs a block after the else
+        if let Some((_, diverge)) = &i.diverge {
+            self.visit_bare_expr(diverge);
+        }
+    }
+
+    fn visit_expr_repeat(&mut self, i: &'_ syn::ExprRepeat) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_expr(&i.expr);
+        self.visit_array_len(&i.len);
+    }
+
+    fn visit_type_array(&mut self, i: &'_ syn::TypeArray) {
+        self.visit_type(&i.elem);
+        self.visit_array_len(&i.len);
+    }
+
+    fn visit_type_tuple(&mut self, i: &'_ syn::TypeTuple) {
+        // Like tuple expressions, (T) is never a tuple type, so this doesn't
+        // change what it means
+        visit::visit_type_tuple(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_type_bare_fn(&mut self, i: &'_ syn::TypeBareFn) {
+        visit::visit_type_bare_fn(self, i);
+        self.optional_separator(&i.inputs, &[","]);
+    }
+
+    fn visit_angle_bracketed_generic_arguments(
+        &mut self,
+        i: &'_ syn::AngleBracketedGenericArguments,
+    ) {
+        // Vec<u8> can become Vec::<u8,>
+        if i.colon2_token.is_none() {
+            self.optional_before(&i.lt_token, &["::"]);
+        }
+        visit::visit_angle_bracketed_generic_arguments(self, i);
+        self.optional_separator(&i.args, &[","]);
+    }
+
+    fn visit_parenthesized_generic_arguments(&mut self, i: &'_ syn::ParenthesizedGenericArguments) {
+        visit::visit_parenthesized_generic_arguments(self, i);
+        self.optional_separator(&i.inputs, &[","]);
+    }
+
+    fn visit_local(&mut self, i: &'_ syn::Local) {
+        visit::visit_local(self, i);
+        // let x = 1 can become let x:_ = 1
+        if !matches!(i.pat, Pat::Type(_)) {
+            self.optional_after(&i.pat, &[":_"]);
+        }
+    }
+
+    fn visit_use_path(&mut self, i: &'_ syn::UsePath) {
+        // use a::b can become use a::{b}
+        self.visit_ident(&i.ident);
+        self.wrap_use_tree(&i.tree);
+    }
+
+    fn visit_use_group(&mut self, i: &'_ syn::UseGroup) {
+        for tree in &i.items {
+            self.wrap_use_tree(tree);
+        }
+    }
+
+    fn visit_expr_if(&mut self, i: &'_ syn::ExprIf) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_expr(&i.cond);
+        self.visit_block(&i.then_branch);
+        // else has to be followed by a block or another if
+        if let Some((_, else_branch)) = &i.else_branch {
+            self.visit_bare_expr(else_branch);
+        }
+    }
+
+    fn visit_expr_assign(&mut self, i: &'_ syn::ExprAssign) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_assignee(&i.left);
+        self.visit_expr(&i.right);
+    }
+
+    fn visit_expr_match(&mut self, i: &'_ syn::ExprMatch) {
+        visit::visit_expr_match(self, i);
+        if let Some(arm) = i.arms.last().filter(|arm| arm.comma.is_none()) {
+            self.optional_after(&arm.body, &[","]);
+        }
+    }
+
+    fn visit_type_param(&mut self, i: &'_ syn::TypeParam) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_ident(&i.ident);
+        self.wrap_bounds(&i.bounds);
+        if let Some(default) = &i.default {
+            self.visit_type(default);
+        }
+        self.optional_separator(&i.bounds, &["+"]);
+    }
+
+    fn visit_predicate_type(&mut self, i: &'_ syn::PredicateType) {
+        if let Some(lifetimes) = &i.lifetimes {
+            self.visit_bound_lifetimes(lifetimes);
+        }
+        self.visit_type(&i.bounded_ty);
+        self.wrap_bounds(&i.bounds);
+        self.optional_separator(&i.bounds, &["+"]);
+    }
+
+    fn visit_item_trait(&mut self, i: &'_ syn::ItemTrait) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_visibility(&i.vis);
+        self.visit_ident(&i.ident);
+        self.visit_generics(&i.generics);
+        self.wrap_bounds(&i.supertraits);
+        for item in &i.items {
+            self.visit_trait_item(item);
+        }
+        self.optional_separator(&i.supertraits, &["+"]);
+    }
+
+    fn visit_trait_item_type(&mut self, i: &'_ syn::TraitItemType) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_ident(&i.ident);
+        self.visit_generics(&i.generics);
+        self.wrap_bounds(&i.bounds);
+        if let Some((_, default)) = &i.default {
+            self.visit_type(default);
+        }
+        self.optional_separator(&i.bounds, &["+"]);
+    }
+
+    fn visit_attribute(&mut self, i: &'_ syn::Attribute) {
+        self.in_attribute = true;
+        visit::visit_attribute(self, i);
+        self.in_attribute = false;
+    }
+
+    fn visit_receiver(&mut self, i: &'_ syn::Receiver) {
+        // `&self` has a type, but it isn't written out
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+    }
+
+    fn visit_qself(&mut self, _: &'_ syn::QSelf) {
+        // <T as Trait>::Type is left alone
+    }
+
+    fn visit_item_impl(&mut self, i: &'_ syn::ItemImpl) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_generics(&i.generics);
+        if let Some((_, path, _)) = &i.trait_ {
+            self.visit_path(path);
+        }
+        self.visit_type(&i.self_ty);
+        for item in &i.items {
+            self.visit_impl_item(item);
+            // Items of trait impls can't have a visibility
+            if let (None, syn::ImplItem::Fn(item)) = (&i.trait_, item) {
+                self.optional_visibility(&item.vis, &item.sig);
+            }
+        }
+    }
+
+    fn visit_field(&mut self, i: &'_ syn::Field) {
+        self.attributable(i);
+        // struct S(pub (T)) would be read as a visibility
+        if i.ident.is_none() && matches!(i.vis, Visibility::Public(_)) {
+            for attr in &i.attrs {
+                self.visit_attribute(attr);
+            }
+            visit::visit_type(self, &i.ty);
+        } else {
+            visit::visit_field(self, i);
+        }
+    }
+
+    fn visit_const_param(&mut self, i: &'_ syn::ConstParam) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_type(&i.ty);
+        // Like a generic argument, a default needs braces
+        if let Some(default) = &i.default {
+            self.visit_const(default, |this| this.visit_bare_expr(default));
+        }
+    }
+
+    fn visit_generic_argument(&mut self, i: &'_ syn::GenericArgument) {
+        // Constant expressions need braces, not parentheses
+        if let GenericArgument::Const(expr) = i {
+            self.visit_const(expr, |this| this.visit_bare_expr(expr));
+            return;
+        }
+        // A constant passed as a generic argument is parsed as a type, but
+        // would become one if it were wrapped
+        if let GenericArgument::Type(Type::Path(path)) = i {
+            if let Some(ident) = path.path.get_ident() {
+                let name = ident.to_string();
+                let screaming = name.len() > 1
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+                if screaming || self.consts.contains(&name) {
+                    return;
+                }
+            }
+        }
+        visit::visit_generic_argument(self, i);
+    }
+
+    fn visit_type(&mut self, i: &'_ syn::Type) {
+        match i {
+            Type::Array(_)
+            | Type::BareFn(_)
+            | Type::ImplTrait(_)
+            | Type::Infer(_)
+            | Type::Macro(_)
+            | Type::Paren(_)
+            | Type::Path(_)
+            | Type::Ptr(_)
+            | Type::Reference(_)
+            | Type::Slice(_)
+            | Type::TraitObject(_)
+            | Type::Tuple(_) => {
+                self.wrap_type(i);
+            }
+            // (!) is still unstable
+            Type::Group(_) | Type::Never(_) | Type::Verbatim(_) => {
+                visit::visit_type(self, i);
+            }
+            _ => panic!("new type variant"),
+        }
+    }
+
+    fn visit_item_fn(&mut self, i: &'_ syn::ItemFn) {
+        self.optional_visibility(&i.vis, &i.sig);
+        match i.sig.constness {
+            Some(_) => self.visit_const(i, |this| visit::visit_item_fn(this, i)),
+            None => visit::visit_item_fn(self, i),
+        }
+    }
+
+    fn visit_impl_item_fn(&mut self, i: &'_ syn::ImplItemFn) {
+        match i.sig.constness {
+            Some(_) => self.visit_const(i, |this| visit::visit_impl_item_fn(this, i)),
+            None => visit::visit_impl_item_fn(self, i),
+        }
+    }
+
+    fn visit_item_const(&mut self, i: &'_ syn::ItemConst) {
+        self.visit_const(i, |this| visit::visit_item_const(this, i));
+    }
+
+    fn visit_item_static(&mut self, i: &'_ syn::ItemStatic) {
+        self.visit_const(i, |this| visit::visit_item_static(this, i));
+    }
+
+    fn visit_impl_item_const(&mut self, i: &'_ syn::ImplItemConst) {
+        self.visit_const(i, |this| visit::visit_impl_item_const(this, i));
+    }
+
+    fn visit_trait_item_const(&mut self, i: &'_ syn::TraitItemConst) {
+        self.visit_const(i, |this| visit::visit_trait_item_const(this, i));
+    }
+
+    fn visit_expr_const(&mut self, i: &'_ syn::ExprConst) {
+        self.visit_const(i, |this| visit::visit_expr_const(this, i));
+    }
+
+    fn visit_signature(&mut self, i: &'_ syn::Signature) {
+        visit::visit_signature(self, i);
+        // After visiting, so the text goes outside parentheses around the
+        // return type
+        self.pad_signature(i);
+    }
+
+    fn visit_foreign_item_fn(&mut self, i: &'_ syn::ForeignItemFn) {
+        self.optional_visibility(&i.vis, &i.sig);
+        self.in_declaration = true;
+        visit::visit_foreign_item_fn(self, i);
+        self.in_declaration = false;
+    }
+
+    fn visit_trait_item_fn(&mut self, i: &'_ syn::TraitItemFn) {
+        self.in_declaration = i.default.is_none();
+        visit::visit_trait_item_fn(self, i);
+        self.in_declaration = false;
+    }
+
+    fn visit_variant(&mut self, i: &'_ syn::Variant) {
+        self.attributable(i);
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_ident(&i.ident);
+        self.visit_fields(&i.fields);
+        if let Some((_, discriminant)) = &i.discriminant {
+            self.visit_const(discriminant, |this| this.visit_expr(discriminant));
+        }
+    }
+
+    fn visit_fn_arg(&mut self, i: &'_ syn::FnArg) {
+        self.attributable(i);
+        visit::visit_fn_arg(self, i);
+    }
+
+    fn visit_expr_closure(&mut self, i: &'_ syn::ExprClosure) {
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        if let Some(lifetimes) = &i.lifetimes {
+            self.visit_bound_lifetimes(lifetimes);
+        }
+        for input in &i.inputs {
+            self.attributable(input);
+            self.visit_pat(input);
+        }
+        self.visit_return_type(&i.output);
+        // A closure with a return type needs a block for a body
+        if let ReturnType::Type(..) = i.output {
+            self.bare = true;
+        }
+        self.wrap_block(&i.body);
+    }
+
+    fn visit_arm(&mut self, i: &'_ syn::Arm) {
+        self.attributable(i);
+        for attr in &i.attrs {
+            self.visit_attribute(attr);
+        }
+        self.visit_pat(&i.pat);
+        if let Some((_, guard)) = &i.guard {
+            self.visit_expr(guard);
+        }
+        // Without a comma, the body is a block-like expression that the
+        // next arm can directly follow
+        if i.comma.is_none() {
+            self.bare = true;
+        }
+        self.wrap_block(&i.body);
+    }
+
+    fn visit_ident(&mut self, i: &'_ proc_macro2::Ident) {
+        // Raw identifiers work in every edition, even for names that are
+        // only keywords in some, but not for path keywords or `_`.
+        // `macro_rules` is only special when it isn't raw.
+        let name = i.to_string();
+        let start = first_token_start(i);
+        if !self.in_attribute
+            && !self.metavars.contains(&start)
+            && !name.starts_with("r#")
+            && !["self", "Self", "super", "crate", "_", "macro_rules"].contains(&name.as_str())
+        {
+            let TokenStart { line, char } = start;
+            self.events
+                .push(lex::Spanned::new(Event::Ident, line, char));
+        }
+    }
+
+    fn visit_macro(&mut self, i: &'_ syn::Macro) {
+        visit::visit_macro(self, i);
+        if !self.in_macro_rules {
+            self.space_macro(&i.tokens);
+        }
+        if i.path.is_ident("macro_rules") {
+            self.visit_macro_rules(&i.tokens);
+            return;
+        }
+        if self.in_attribute {
+            return;
+        }
+        let Some(name) = i
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+        else {
+            return;
+        };
+        let Some(grammar) = Grammar::of(&name).filter(|_| !self.macros.contains(&name)) else {
+            return;
+        };
+        // Arguments the macro wouldn't accept are left alone
+        let Ok(args) = i.parse_body_with(|input: ParseStream| grammar.parse(input)) else {
+            return;
+        };
+
+        for expr in &args.exprs {
+            self.visit_expr(expr);
+        }
+        if let Some((pat, guard)) = &args.pattern {
+            self.visit_pat(pat);
+            if let Some(guard) = guard {
+                self.visit_expr(guard);
+            }
+        }
+        if let Some(TokenStart { line, char }) = args.trailing {
+            self.events
+                .push(lex::Spanned::new(Event::Optional(&[","]), line, char));
+        }
+    }
+
+    fn visit_lifetime(&mut self, _: &'_ syn::Lifetime) {
+        // Lifetimes and labels can't be raw
+    }
+
+    fn visit_expr_lit(&mut self, i: &'_ syn::ExprLit) {
+        // Attributes often need the literal exactly as written
+        if !self.in_attribute {
+            let TokenStart { line, char } = first_token_start(i);
+            self.events
+                .push(lex::Spanned::new(Event::Literal, line, char));
+        }
+        visit::visit_expr_lit(self, i);
+    }
+
+    fn visit_field_value(&mut self, i: &'_ syn::FieldValue) {
+        // S { x } can't become S { {x} } or S { (x) }, but it can become
+        // S { x: (x) }
+        if i.colon_token.is_none() {
+            for attr in &i.attrs {
+                self.visit_attribute(attr);
+            }
+            self.shorthand(&i.member);
+            self.visit_bare_expr(&i.expr);
+        } else {
+            for attr in &i.attrs {
+                self.visit_attribute(attr);
+            }
+            self.visit_member(&i.member);
+            self.wrap_block(&i.expr);
+        }
+    }
+
+    fn visit_field_pat(&mut self, i: &'_ syn::FieldPat) {
+        // S { x } stays as it is, since S { (x) } and S { x@_ } aren't
+        // patterns
+        if i.colon_token.is_none() {
+            for attr in &i.attrs {
+                self.visit_attribute(attr);
+            }
+            visit::visit_pat(self, &i.pat);
+        } else {
+            visit::visit_field_pat(self, i);
+        }
+    }
+
+    fn visit_pat(&mut self, i: &'_ syn::Pat) {
+        let nested = std::mem::replace(&mut self.in_pattern, true);
+        match i {
+            // The parameters of a function without a body can't be patterns
+            _ if self.in_declaration => visit::visit_pat(self, i),
+            // .. and x@.. are only allowed directly in a tuple or slice
+            Pat::Rest(_) => {}
+            Pat::Ident(ident)
+                if ident
+                    .subpat
+                    .as_ref()
+                    .is_some_and(|(_, pat)| matches!(**pat, Pat::Rest(_))) => {}
+            // 1.. would end partway through a token, and syn makes -1 a single
+            // token that starts at the minus sign
+            Pat::Range(range) if range.end.as_deref().map_or(true, is_negative) => {}
+            Pat::Lit(lit) if lit.lit.to_token_stream().to_string().starts_with('-') => {}
+            // (x: T) isn't a pattern, and A | B is only wrapped inside another
+            // pattern, where it usually has to be already
+            Pat::Type(_) | Pat::Verbatim(_) => visit::visit_pat(self, i),
+            Pat::Or(_) if !nested => visit::visit_pat(self, i),
+            _ => self.wrap_pat(i),
+        }
+        self.in_pattern = nested;
+    }
+
+    fn visit_pat_tuple(&mut self, i: &'_ syn::PatTuple) {
+        // Like tuple expressions, (x) is never a tuple pattern
+        visit::visit_pat_tuple(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_pat_tuple_struct(&mut self, i: &'_ syn::PatTupleStruct) {
+        visit::visit_pat_tuple_struct(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_pat_slice(&mut self, i: &'_ syn::PatSlice) {
+        visit::visit_pat_slice(self, i);
+        self.optional_separator(&i.elems, &[","]);
+    }
+
+    fn visit_pat_struct(&mut self, i: &'_ syn::PatStruct) {
+        visit::visit_pat_struct(self, i);
+        // Nothing can come after ..
+        if i.rest.is_none() {
+            self.optional_separator(&i.fields, &[","]);
+        }
+    }
+
     fn visit_expr(&mut self, i: &'_ syn::Expr) {
+        // Attributes need literals, and some places need a particular kind of
+        // expression
+        if std::mem::take(&mut self.bare) || self.in_attribute {
+            visit::visit_expr(self, i);
+            return;
+        }
         match i {
             syn::Expr::Array(_)
             | syn::Expr::Assign(_)
+            | syn::Expr::Async(_)
             | syn::Expr::Await(_)
             | syn::Expr::Binary(_)
+            | syn::Expr::Block(_)
             | syn::Expr::Break(_)
             | syn::Expr::Call(_)
             | syn::Expr::Cast(_)
             | syn::Expr::Closure(_)
+            | syn::Expr::Const(_)
             | syn::Expr::Continue(_)
+            | syn::Expr::Field(_)
+            | syn::Expr::ForLoop(_)
+            | syn::Expr::If(_)
             | syn::Expr::Index(_)
-            | syn::Expr::Infer(_)
+            | syn::Expr::Loop(_)
             | syn::Expr::Macro(_)
+            | syn::Expr::Match(_)
             | syn::Expr::MethodCall(_)
             | syn::Expr::Paren(_)
+            | syn::Expr::Path(_)
+            | syn::Expr::Range(_)
             | syn::Expr::Reference(_)
             | syn::Expr::Repeat(_)
             | syn::Expr::Return(_)
             | syn::Expr::Struct(_)
             | syn::Expr::Try(_)
+            | syn::Expr::TryBlock(_)
             | syn::Expr::Tuple(_)
             | syn::Expr::Unary(_)
+            | syn::Expr::Unsafe(_)
+            | syn::Expr::While(_)
             | syn::Expr::Yield(_) => {
                 let (start, end) = expr_endpoints(i);
                 self.events.push(start);
                 visit::visit_expr(self, i);
                 self.events.push(end);
             },
-            syn::Expr::Lit(_)  => {
+            syn::Expr::Lit(lit)  => {
                 // syn doesn't understand doc comments, so they appear as expressions
                 // starting at character 1
                 let (start, end) = expr_endpoints(i);
@@ -79,30 +1112,21 @@ impl Visit<'_> for Visitor {
                     visit::visit_expr(self, i);
                 } else {
                     self.events.push(start);
+                    // Attributes often need a literal, not an expression
+                    if !self.in_attribute && is_raw_string(&lit.lit) {
+                        let TokenStart { line, char } = first_token_start(i);
+                        self.events.push(lex::Spanned::new(Event::Concat, line, char));
+                    }
                     visit::visit_expr(self, i);
                     self.events.push(end);
                 }
             },
-            // A path of length 1 is just an identifier, however these cannot
-            // always be wrapped in parentheses because of struct initalizers:
-            // X { a, b } cannot be converted to X { (a), (b) }
-            // Other paths of length greater than 1 cannot be wrapped either
-            syn::Expr::Path(_)
-            | syn::Expr::Block(_)
-            | syn::Expr::Group(_) // not sure what this is
-            | syn::Expr::Range(_) // dot tokens handled weirdly
-            | syn::Expr::Field(_) // also something weird going on here
+            // `let` only works directly in a condition, and `_` only where a
+            // pattern or a type could be inferred
+            syn::Expr::Group(_) // not sure what this is
+            | syn::Expr::Infer(_)
             | syn::Expr::Verbatim(_)
-            | syn::Expr::Match(_)
-            | syn::Expr::Let(_)
-            | syn::Expr::Async(_)
-            | syn::Expr::Const(_)
-            | syn::Expr::ForLoop(_)
-            | syn::Expr::If(_)
-            | syn::Expr::Loop(_)
-            | syn::Expr::TryBlock(_)
-            | syn::Expr::Unsafe(_)
-            | syn::Expr::While(_) => {
+            | syn::Expr::Let(_) => {
                 visit::visit_expr(self, i);
             }
             _ => panic!("new expression variant"),
@@ -116,6 +1140,587 @@ pub enum Event {
     StatementEnd,
     ExprOpen,
     ExprClose,
+    /// Start of a type that can be wrapped in parentheses
+    TypeOpen,
+    /// End of a type that can be wrapped in parentheses
+    TypeClose,
+    /// Start of a trait bound that can be wrapped in parentheses once
+    BoundOpen,
+    /// End of a trait bound that can be wrapped in parentheses once
+    BoundClose,
+    /// Start of a pattern that can be wrapped in parentheses
+    PatOpen,
+    /// End of a pattern that can be wrapped in parentheses
+    PatClose,
+    /// Text that can be inserted after a token without changing the program,
+    /// such as a trailing comma. At most one of the choices is used.
+    Optional(&'static [&'static str]),
+    /// Text that can be inserted before a token without changing the program,
+    /// such as a visibility qualifier. At most one of the choices is used.
+    OptionalBefore(&'static [&'static str]),
+    /// Start of an expression used as a value, which can be wrapped in a block
+    /// with junk statements before it
+    BlockOpen,
+    /// End of an expression that can be wrapped in a block
+    BlockClose,
+    /// Start of an item directly inside the file, which is pinned if moving
+    /// it could change what the file means.
+    ItemStart {
+        pinned: bool,
+    },
+    /// Start of an item directly inside a module, where junk items can go.
+    /// Points at the name of a type in the module that they can implement.
+    ModuleItemStart(Option<TokenStart>),
+    /// End of an item directly inside a module.
+    ModuleItemEnd(Option<TokenStart>),
+    /// Start of a field, variant, parameter or match arm, where attributes can
+    /// go.
+    Attributable,
+    /// Start of a statement or nested item.
+    Anchor,
+    /// Start of a raw string in expression position, which can be replaced by
+    /// a concat! of smaller strings.
+    Concat,
+    /// Start of a literal in an expression or pattern, which can be written
+    /// longer, or split across lines, without changing its value.
+    Literal,
+    /// Start of an identifier that can be written as a raw identifier.
+    Ident,
+    /// Start of a use tree that can be wrapped in braces
+    UseOpen,
+    /// End of a use tree that can be wrapped in braces
+    UseClose,
+    /// A shorthand field, which can be spelled out in full.
+    Shorthand,
+    /// Start of a token in a macro that needs a space before it.
+    Spacer,
+    /// Start of code evaluated at compile time
+    ConstOpen,
+    /// End of code evaluated at compile time
+    ConstClose,
+}
+
+/// Names of macros defined with `macro_rules!`.
+#[derive(Default)]
+struct Macros(HashSet<String>);
+
+impl Visit<'_> for Macros {
+    fn visit_item_macro(&mut self, i: &'_ syn::ItemMacro) {
+        if let Some(ident) = &i.ident {
+            self.0.insert(ident.to_string());
+        }
+    }
+}
+
+/// Names of constants and statics, which can be used as patterns, and of
+/// const generics, which look like types when used as generic arguments.
+#[derive(Default)]
+struct Consts(HashSet<String>);
+
+impl Visit<'_> for Consts {
+    fn visit_const_param(&mut self, i: &'_ syn::ConstParam) {
+        self.0.insert(i.ident.to_string());
+    }
+
+    fn visit_impl_item_const(&mut self, i: &'_ syn::ImplItemConst) {
+        self.0.insert(i.ident.to_string());
+        visit::visit_impl_item_const(self, i);
+    }
+
+    fn visit_trait_item_const(&mut self, i: &'_ syn::TraitItemConst) {
+        self.0.insert(i.ident.to_string());
+        visit::visit_trait_item_const(self, i);
+    }
+
+    fn visit_item_const(&mut self, i: &'_ syn::ItemConst) {
+        self.0.insert(i.ident.to_string());
+        visit::visit_item_const(self, i);
+    }
+
+    fn visit_item_static(&mut self, i: &'_ syn::ItemStatic) {
+        self.0.insert(i.ident.to_string());
+        visit::visit_item_static(self, i);
+    }
+}
+
+/// How the arguments of a macro from the standard library are laid out.
+#[derive(Debug, Clone, Copy)]
+enum Grammar {
+    /// Expressions separated by commas, or an expression repeated, like
+    /// `vec![a, b]` or `vec![a; n]`
+    List,
+    /// This many expressions, then a format string and its arguments, like
+    /// `assert_eq!(a, b, "{}", c)` with 2
+    Format(usize),
+    /// An expression, then a pattern with an optional guard
+    Matches,
+}
+
+/// Parts of the arguments of a macro that can be padded.
+#[derive(Default)]
+struct MacroArgs {
+    /// Expressions, and values of named format arguments
+    exprs: Vec<Expr>,
+    /// The pattern of `matches!`, and its guard
+    pattern: Option<(Pat, Option<Expr>)>,
+    /// Start of the last token, if a comma can go after it
+    trailing: Option<TokenStart>,
+}
+
+impl Grammar {
+    /// The grammar of the standard library macro called `name`. Macros that
+    /// print their arguments as written, like `assert!` and `dbg!`, aren't
+    /// padded, since that would change what they print.
+    fn of(name: &str) -> Option<Grammar> {
+        match name {
+            "vec" => Some(Grammar::List),
+            "print" | "println" | "eprint" | "eprintln" | "format" | "format_args" | "panic"
+            | "todo" | "unimplemented" | "unreachable" => Some(Grammar::Format(0)),
+            "write" | "writeln" => Some(Grammar::Format(1)),
+            "assert_eq" | "assert_ne" | "debug_assert_eq" | "debug_assert_ne" => {
+                Some(Grammar::Format(2))
+            }
+            "matches" => Some(Grammar::Matches),
+            _ => None,
+        }
+    }
+
+    fn parse(self, input: ParseStream) -> syn::Result<MacroArgs> {
+        let mut args = MacroArgs::default();
+        match self {
+            Grammar::List => {
+                let tokens = input.parse::<proc_macro2::TokenStream>()?;
+                let array = TokenTree::Group(proc_macro2::Group::new(
+                    proc_macro2::Delimiter::Bracket,
+                    tokens,
+                ));
+                match syn::parse2(array.into())? {
+                    Expr::Array(array) => {
+                        if !array.elems.trailing_punct() {
+                            args.trailing = array.elems.last().map(last_token_start);
+                        }
+                        args.exprs.extend(array.elems);
+                    }
+                    Expr::Repeat(repeat) => args.exprs.extend([*repeat.expr, *repeat.len]),
+                    _ => unreachable!("brackets parse as an array"),
+                }
+            }
+            Grammar::Format(leading) => {
+                let list = Punctuated::<Expr, syn::Token![,]>::parse_terminated(input)?;
+                if !list.trailing_punct() {
+                    args.trailing = list.last().map(last_token_start);
+                }
+                // The format string has to stay a literal, and named
+                // arguments a name
+                for (i, expr) in list.into_iter().enumerate() {
+                    match expr {
+                        _ if i == leading => {}
+                        Expr::Assign(assign) if i > leading => args.exprs.push(*assign.right),
+                        expr => args.exprs.push(expr),
+                    }
+                }
+            }
+            Grammar::Matches => {
+                let expr = input.parse::<Expr>()?;
+                input.parse::<syn::Token![,]>()?;
+                let pat = Pat::parse_multi_with_leading_vert(input)?;
+                let guard = match input.parse::<Option<syn::Token![if]>>()? {
+                    Some(_) => Some(input.parse::<Expr>()?),
+                    None => None,
+                };
+                if input.parse::<Option<syn::Token![,]>>()?.is_none() {
+                    args.trailing = Some(match &guard {
+                        Some(guard) => last_token_start(guard),
+                        None => last_token_start(&pat),
+                    });
+                }
+                args.exprs.push(expr);
+                args.pattern = Some((pat, guard));
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Fragments that parse as one piece wherever they're used, so a
+/// metavariable of that kind can stand in for an identifier.
+const WHOLE_FRAGMENTS: [&str; 9] = [
+    "block",
+    "expr",
+    "expr_2021",
+    "ident",
+    "literal",
+    "pat",
+    "pat_param",
+    "path",
+    "ty",
+];
+
+/// Text of each token in `tokens` as the lexer sees it, with the spans of
+/// its first and last characters. Punctuation that's joined, like `=>`, and
+/// metavariables and lifetimes, like `$x` and `'a`, are one token.
+fn lexer_tokens(tokens: TokenStream, out: &mut Vec<(String, Span, Span)>) {
+    let mut joined = false;
+    for tree in tokens {
+        let span = match &tree {
+            TokenTree::Group(group) => group.span_open(),
+            _ => tree.span(),
+        };
+        let text = match &tree {
+            TokenTree::Group(group) => match group.delimiter() {
+                proc_macro2::Delimiter::Parenthesis => "(",
+                proc_macro2::Delimiter::Brace => "{",
+                proc_macro2::Delimiter::Bracket => "[",
+                proc_macro2::Delimiter::None => "",
+            }
+            .to_string(),
+            _ => tree.to_string(),
+        };
+
+        match out.last_mut() {
+            Some((last, _, end)) if joined && end.end() == span.start() => {
+                last.push_str(&text);
+                *end = span;
+            }
+            _ => out.push((text, span, span)),
+        }
+        joined = match &tree {
+            TokenTree::Punct(punct) => {
+                punct.spacing() == Spacing::Joint || matches!(punct.as_char(), '$' | '\'')
+            }
+            _ => false,
+        };
+
+        if let TokenTree::Group(group) = tree {
+            lexer_tokens(group.stream(), out);
+            let close = group.span_close();
+            let text = match group.delimiter() {
+                proc_macro2::Delimiter::Parenthesis => ")",
+                proc_macro2::Delimiter::Brace => "}",
+                proc_macro2::Delimiter::Bracket => "]",
+                proc_macro2::Delimiter::None => "",
+            };
+            out.push((text.to_string(), close, close));
+        }
+    }
+}
+
+/// Kinds of the fragments bound in a macro matcher, by name.
+fn collect_fragments(tokens: TokenStream, fragments: &mut HashMap<String, String>) {
+    let trees = tokens.into_iter().collect::<Vec<_>>();
+    for (i, tree) in trees.iter().enumerate() {
+        match (tree, trees.get(i + 1), trees.get(i + 2), trees.get(i + 3)) {
+            (
+                TokenTree::Punct(dollar),
+                Some(TokenTree::Ident(name)),
+                Some(TokenTree::Punct(colon)),
+                Some(TokenTree::Ident(kind)),
+            ) if dollar.as_char() == '$' && colon.as_char() == ':' => {
+                fragments.insert(name.to_string(), kind.to_string());
+            }
+            (TokenTree::Group(group), ..) => collect_fragments(group.stream(), fragments),
+            _ => {}
+        }
+    }
+}
+
+/// `tokens` from a transcriber with each metavariable replaced by an
+/// identifier at the `$`, or `None` if it repeats or uses a fragment that
+/// doesn't parse as one piece. The starts of the replacements go in
+/// `metavars`.
+fn substitute(
+    tokens: TokenStream,
+    fragments: &HashMap<String, String>,
+    metavars: &mut Vec<TokenStart>,
+) -> Option<TokenStream> {
+    let mut out = vec![];
+    let mut trees = tokens.into_iter();
+    while let Some(tree) = trees.next() {
+        match tree {
+            TokenTree::Punct(dollar) if dollar.as_char() == '$' => {
+                let Some(TokenTree::Ident(name)) = trees.next() else {
+                    return None;
+                };
+                let whole = fragments
+                    .get(&name.to_string())
+                    .is_some_and(|kind| WHOLE_FRAGMENTS.contains(&kind.as_str()));
+                if !whole && name != "crate" {
+                    return None;
+                }
+                metavars.push(TokenStart::from(dollar.span()));
+                out.push(TokenTree::Ident(proc_macro2::Ident::new(
+                    &name.to_string(),
+                    dollar.span(),
+                )));
+            }
+            TokenTree::Group(group) => {
+                let stream = substitute(group.stream(), fragments, metavars)?;
+                let mut substituted = proc_macro2::Group::new(group.delimiter(), stream);
+                substituted.set_span(group.span());
+                out.push(TokenTree::Group(substituted));
+            }
+            tree => out.push(tree),
+        }
+    }
+    Some(out.into_iter().collect())
+}
+
+/// Whether a macro expanding to `expr` can only be used as a value. Padding
+/// for expressions could break one used as a type or a pattern, like `(a, b)`
+/// or `A | B`.
+fn is_value(expr: &Expr) -> bool {
+    match expr {
+        Expr::Assign(_)
+        | Expr::Block(_)
+        | Expr::Cast(_)
+        | Expr::Closure(_)
+        | Expr::ForLoop(_)
+        | Expr::If(_)
+        | Expr::Loop(_)
+        | Expr::Match(_)
+        | Expr::MethodCall(_)
+        | Expr::Unsafe(_)
+        | Expr::While(_) => true,
+        Expr::Binary(binary) => !matches!(binary.op, syn::BinOp::BitOr(_)),
+        _ => false,
+    }
+}
+
+/// Whether moving `item` could change what the file means. Macros are only
+/// visible after they're defined, and an item macro could define one.
+fn is_pinned(item: &syn::Item) -> bool {
+    let macro_use =
+        |attrs: &[syn::Attribute]| attrs.iter().any(|attr| attr.path().is_ident("macro_use"));
+    match item {
+        syn::Item::Macro(_) | syn::Item::Verbatim(_) => true,
+        syn::Item::Mod(item) => macro_use(&item.attrs),
+        syn::Item::ExternCrate(item) => macro_use(&item.attrs),
+        _ => false,
+    }
+}
+
+/// Name of the type among `items` with the shortest name that empty `impl`
+/// blocks can be written for, which needs it to have no generics and always
+/// exist.
+fn local_type(items: &[syn::Item]) -> Option<TokenStart> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Struct(syn::ItemStruct {
+                attrs,
+                ident,
+                generics,
+                ..
+            })
+            | syn::Item::Enum(syn::ItemEnum {
+                attrs,
+                ident,
+                generics,
+                ..
+            })
+            | syn::Item::Union(syn::ItemUnion {
+                attrs,
+                ident,
+                generics,
+                ..
+            }) if generics.params.is_empty()
+                && !attrs.iter().any(|attr| attr.path().is_ident("cfg")) =>
+            {
+                Some(ident)
+            }
+            _ => None,
+        })
+        .min_by_key(|ident| ident.to_string().len())
+        .map(|ident| TokenStart::from(ident.span()))
+}
+
+/// Whether `expr` is a negative literal.
+fn is_negative(expr: &Expr) -> bool {
+    matches!(expr, Expr::Lit(lit) if lit.lit.to_token_stream().to_string().starts_with('-'))
+}
+
+/// Whether `expr` means the same thing wrapped in a block. Blocks move out of
+/// places, so `{x}` can't be used where `x` would be reborrowed, and since
+/// edition 2024 they drop temporaries in their tail, so `{f().as_str()}` would
+/// borrow a value that no longer exists. Only values that can't borrow from a
+/// temporary, like literals, constructors and arithmetic, are wrapped.
+fn is_blockable(expr: &Expr) -> bool {
+    !matches!(
+        expr,
+        Expr::Path(_)
+            | Expr::Field(_)
+            | Expr::Reference(_)
+            | Expr::Unary(syn::ExprUnary {
+                op: UnOp::Deref(_),
+                ..
+            })
+            | Expr::Paren(_)
+    ) && is_owned(expr)
+        && !starts_with_block(expr)
+}
+
+/// Whether `expr` is known not to borrow from a temporary.
+fn is_owned(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(_) | Expr::Path(_) | Expr::Closure(_) | Expr::Binary(_) | Expr::Unary(_) => true,
+        // &temporary() as &dyn Trait still borrows the temporary
+        Expr::Cast(cast) => is_owned(&cast.expr),
+        Expr::Field(field) => is_owned(&field.base),
+        Expr::Paren(paren) => is_owned(&paren.expr),
+        Expr::Repeat(repeat) => is_owned(&repeat.expr),
+        Expr::Range(range) => [&range.start, &range.end]
+            .into_iter()
+            .flatten()
+            .all(|end| is_owned(end)),
+        // A borrow of a place, not of a temporary
+        Expr::Reference(reference) => is_place(&reference.expr),
+        Expr::Tuple(tuple) => tuple.elems.iter().all(is_owned),
+        Expr::Array(array) => array.elems.iter().all(is_owned),
+        Expr::Struct(expr) => {
+            expr.fields.iter().all(|field| is_owned(&field.expr))
+                && expr.rest.as_deref().map_or(true, is_owned)
+        }
+        // Tuple structs and variants, but not functions, whose result can
+        // borrow from their arguments
+        Expr::Call(call) => {
+            matches!(&*call.func, Expr::Path(path) if path.path.segments.last().is_some_and(|segment| {
+                segment.ident.to_string().starts_with(char::is_uppercase)
+            })) && call.args.iter().all(is_owned)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `expr` names a place rooted in a variable, like `x` or `x.y[0]`.
+fn is_place(expr: &Expr) -> bool {
+    match expr {
+        Expr::Path(_) => true,
+        Expr::Field(field) => is_place(&field.base),
+        Expr::Index(index) => is_place(&index.expr) && is_owned(&index.index),
+        Expr::Paren(paren) => is_place(&paren.expr),
+        _ => false,
+    }
+}
+
+/// Whether `expr` starts with a block-like expression, such as `if x {} + y`.
+/// At the start of a statement, the block-like part would be a statement of its
+/// own.
+fn starts_with_block(mut expr: &Expr) -> bool {
+    loop {
+        expr = match expr {
+            Expr::Assign(expr) => &expr.left,
+            Expr::Await(expr) => &expr.base,
+            Expr::Binary(expr) => &expr.left,
+            Expr::Call(expr) => &expr.func,
+            Expr::Cast(expr) => &expr.expr,
+            Expr::Field(expr) => &expr.base,
+            Expr::Index(expr) => &expr.expr,
+            Expr::MethodCall(expr) => &expr.receiver,
+            Expr::Range(syn::ExprRange {
+                start: Some(start), ..
+            }) => start,
+            Expr::Try(expr) => &expr.expr,
+            _ => return false,
+        };
+        if matches!(
+            expr,
+            Expr::Block(_)
+                | Expr::Const(_)
+                | Expr::ForLoop(_)
+                | Expr::If(_)
+                | Expr::Loop(_)
+                | Expr::Match(_)
+                | Expr::TryBlock(_)
+                | Expr::Unsafe(_)
+                | Expr::While(_)
+        ) {
+            return true;
+        }
+    }
+}
+
+/// Whether `tokens` end in tuple indices that the lexer sees as a float.
+/// Indices joined by dots are lexed in pairs, so `x.0.1.2` is `x`, `.`, `0.1`,
+/// `.` and `2`.
+fn ends_in_float(tokens: &[TokenTree]) -> bool {
+    let mut indices = 0;
+    let mut rest = tokens;
+    while let [init @ .., TokenTree::Punct(dot), TokenTree::Literal(index)] = rest {
+        if dot.as_char() != '.' || !is_index(index) {
+            break;
+        }
+        indices += 1;
+        match init {
+            [.., TokenTree::Literal(prev)]
+                if is_index(prev)
+                    && joined(prev.span(), prev.to_string().len(), dot.span())
+                    && joined(dot.span(), 1, index.span()) =>
+            {
+                rest = init;
+            }
+            _ => break,
+        }
+    }
+    indices > 0 && indices % 2 == 0
+}
+
+/// Whether `literal` is a tuple index.
+fn is_index(literal: &proc_macro2::Literal) -> bool {
+    literal.to_string().bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Whether `next` starts right where the `len` chars long token at `span` ends.
+fn joined(span: Span, len: usize, next: Span) -> bool {
+    let start = TokenStart::from(span);
+    let next = TokenStart::from(next);
+    start.line == next.line && start.char + len == next.char
+}
+
+fn is_raw_string(lit: &syn::Lit) -> bool {
+    matches!(lit, syn::Lit::Str(s) if s.token().to_string().starts_with('r'))
+}
+
+/// Start of the first token of a syntax node.
+fn first_token_start(node: &impl ToTokens) -> TokenStart {
+    let tokens = node.to_token_stream().into_iter().collect::<Vec<_>>();
+    TokenStart::from(span_start(tokens.first().unwrap()))
+}
+
+/// Span of the first token of `tree`.
+fn span_start(tree: &TokenTree) -> Span {
+    match tree {
+        TokenTree::Group(group) => group.span_open(),
+        TokenTree::Ident(ident) => ident.span(),
+        TokenTree::Punct(punct) => punct.span(),
+        TokenTree::Literal(literal) => literal.span(),
+    }
+}
+
+/// Start of the last token of a syntax node.
+fn last_token_start(node: &impl ToTokens) -> TokenStart {
+    let tokens = node.to_token_stream().into_iter().collect::<Vec<_>>();
+    TokenStart::from(last_span(&tokens))
+}
+
+/// Span of the last of `tokens`. The `..` at the end of a range is two tokens
+/// to syn, but one to the lexer. The `.1` at the end of `x.0.1` is part of a
+/// float to the lexer, which gets split before it.
+fn last_span(tokens: &[TokenTree]) -> Span {
+    match tokens {
+        [.., TokenTree::Punct(first), TokenTree::Punct(second)]
+            if first.as_char() == '.'
+                && second.as_char() == '.'
+                && first.spacing() == Spacing::Joint =>
+        {
+            first.span()
+        }
+        [.., TokenTree::Punct(dot), TokenTree::Literal(_)] if ends_in_float(tokens) => dot.span(),
+        [.., TokenTree::Group(group)] => group.span_close(),
+        [.., TokenTree::Ident(ident)] => ident.span(),
+        [.., TokenTree::Punct(punct)] => punct.span(),
+        [.., TokenTree::Literal(literal)] => literal.span(),
+        [] => panic!("syntax node has no tokens"),
+    }
 }
 
 fn stmt_endpoints(stmt: &syn::Stmt) -> (lex::Spanned<Event>, lex::Spanned<Event>) {
@@ -163,12 +1768,7 @@ fn expr_endpoints(stmt: &syn::Expr) -> (lex::Spanned<Event>, lex::Spanned<Event>
     let TokenStart {
         line: end_line,
         char: end_char,
-    } = TokenStart::from(match tokens.last().unwrap() {
-        TokenTree::Group(group) => group.span_close(),
-        TokenTree::Ident(ident) => ident.span(),
-        TokenTree::Punct(punct) => punct.span(),
-        TokenTree::Literal(literal) => literal.span(),
-    });
+    } = TokenStart::from(last_span(&tokens));
 
     (
         lex::Spanned::new(Event::ExprOpen, start_line, start_char),
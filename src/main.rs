@@ -1,15 +1,12 @@
 use std::fs;
-#[cfg(target_os = "unix")]
-use std::os::unix::ffi::OsStrExt;
-
-#[cfg(target_os = "windows")]
-use std::os::windows::ffi::OsStrExt;
-
-use std::path::PathBuf;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
 use anstyle::*;
 use anyhow::Context;
-use clap::Arg;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction};
 use regex::bytes::Regex;
 use walkdir::WalkDir;
 
@@ -35,7 +32,7 @@ fn main() -> anyhow::Result<()> {
             .invalid(INVALID)
     };
 
-    let cmd = clap::Command::new("cargo")
+    let mut cmd = clap::Command::new("cargo")
         .author("Felix Prasanna")
         .about("format code into perfect rectangles")
         .bin_name("cargo")
@@ -50,10 +47,95 @@ fn main() -> anyhow::Result<()> {
                     Arg::new("width")
                         .short('w')
                         .long("line-width")
-                        .help("unformat lines to width")
+                        .visible_alias("width")
+                        .value_name("WIDTH")
+                        .help("unformat lines to width, or auto[=MIN..MAX] to pick the best width")
                         .default_value("80")
+                        .value_parser(parse_width),
+                )
+                .arg(
+                    Arg::new("objective")
+                        .long("objective")
+                        .help("what auto width optimizes: comments, junk, square, or aspect=RATIO")
+                        .default_value("comments")
+                        .value_parser(parse_objective),
+                )
+                .arg(
+                    Arg::new("height")
+                        .long("height")
+                        .help("unformat into exactly this many lines")
+                        .conflicts_with("per-item")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("per-item")
+                        .short('p')
+                        .long("per-item")
+                        .help("give each top-level item its own rectangle")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("stable")
+                        .short('s')
+                        .long("stable")
+                        .help("anchor line breaks to items and statements for smaller diffs")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("frame")
+                        .short('f')
+                        .long("frame")
+                        .help("wrap each rectangle in a border of comments")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("acrostic")
+                        .long("acrostic")
+                        .value_name("TEXT")
+                        .help("start consecutive lines with the characters of TEXT")
+                        .conflicts_with_all(["per-item", "stable", "frame", "height"]),
+                )
+                .arg(
+                    Arg::new("max-parens")
+                        .long("max-parens")
+                        .value_name("N")
+                        .help("add at most N pairs of parentheses around an expression")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("max-junk")
+                        .long("max-junk")
+                        .value_name("N")
+                        .help("add at most N junk statements or items in a row")
                         .value_parser(clap::value_parser!(usize)),
                 )
+                .arg(
+                    Arg::new("spread")
+                        .long("spread")
+                        .help("spread junk and parentheses evenly across each line")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("overflow")
+                        .long("overflow")
+                        .help("what to do with tokens longer than the width")
+                        .default_value("allow")
+                        .value_parser(["allow", "split", "error", "widen"]),
+                )
+                .arg(
+                    Arg::new("reorder")
+                        .long("reorder")
+                        .help("reorder top-level items if that packs lines better")
+                        .conflicts_with("stable")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("regroup")
+                        .long("regroup")
+                        .help("split or merge use declarations if that packs lines better")
+                        .conflicts_with("stable")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("ignore")
                         .short('i')
@@ -64,7 +146,7 @@ fn main() -> anyhow::Result<()> {
         )
         .styles(styles);
 
-    let matches = cmd.get_matches();
+    let matches = cmd.get_matches_mut();
     let matches = match matches.subcommand() {
         Some(("unfmt", matches)) => matches,
         _ => unreachable!("clap L"),
@@ -73,8 +155,48 @@ fn main() -> anyhow::Result<()> {
     let search_path = matches
         .get_one::<PathBuf>("PATH")
         .expect("clap handles this");
-    let width = matches.get_one::<usize>("width").expect("default is 80");
+    let width = matches.get_one::<Width>("width").expect("default is 80");
+    if matches!(width, Width::Fixed(_))
+        && matches.value_source("objective") == Some(ValueSource::CommandLine)
+    {
+        cmd.find_subcommand_mut("unfmt")
+            .expect("unfmt is a subcommand")
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--objective only applies with --width auto",
+            )
+            .exit();
+    }
+    let objective = *matches
+        .get_one::<cargo_unfmt::Objective>("objective")
+        .expect("default is comments");
     let re = matches.get_one::<Regex>("ignore");
+    let config = cargo_unfmt::Config {
+        width: match width {
+            Width::Fixed(width) => *width,
+            Width::Auto(widths) => *widths.start(),
+        },
+        item_rectangles: matches.get_flag("per-item"),
+        stable: matches.get_flag("stable"),
+        height: matches.get_one::<usize>("height").copied(),
+        frame: matches.get_flag("frame"),
+        acrostic: matches.get_one::<String>("acrostic").cloned(),
+        max_parens: matches.get_one::<usize>("max-parens").copied(),
+        max_junk: matches.get_one::<usize>("max-junk").copied(),
+        spread: matches.get_flag("spread"),
+        overflow: match matches
+            .get_one::<String>("overflow")
+            .expect("default is allow")
+            .as_str()
+        {
+            "split" => cargo_unfmt::Overflow::Split,
+            "error" => cargo_unfmt::Overflow::Error,
+            "widen" => cargo_unfmt::Overflow::Widen,
+            _ => cargo_unfmt::Overflow::Allow,
+        },
+        reorder: matches.get_flag("reorder"),
+        regroup: matches.get_flag("regroup"),
+    };
 
     for file in WalkDir::new(search_path) {
         let file = file.context("failed to walkdir file")?;
@@ -100,7 +222,13 @@ fn main() -> anyhow::Result<()> {
                 .with_context(|| format!("failed to read source file: {path:?}"))?;
 
             if src.starts_with('\u{feff}') {
-                match cargo_unfmt::unformat(&src['\u{feff}'.len_utf8()..], *width) {
+                match unformat(
+                    path,
+                    &src['\u{feff}'.len_utf8()..],
+                    &config,
+                    width,
+                    objective,
+                ) {
                     Ok(unformatted) => {
                         let mut unformatted_with_bom = String::from("\u{feff}").into_bytes();
                         unformatted_with_bom.extend(unformatted);
@@ -110,7 +238,7 @@ fn main() -> anyhow::Result<()> {
                     Err(e) => eprintln!("[cargo-unfmt] error on {path:?}: {e}"),
                 }
             } else {
-                match cargo_unfmt::unformat(&src, *width) {
+                match unformat(path, &src, &config, width, objective) {
                     Ok(unformatted) => fs::write(path, &unformatted)
                         .context("failed to write formatted source over")?,
                     Err(e) => eprintln!("[cargo-unfmt] error on {path:?}: {e}"),
@@ -121,3 +249,95 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[derive(Debug, Clone)]
+enum Width {
+    Fixed(usize),
+    /// Try every width in the range and keep the best
+    Auto(RangeInclusive<usize>),
+}
+
+fn parse_width(s: &str) -> Result<Width, String> {
+    let Some(auto) = s.strip_prefix("auto") else {
+        return match s.parse() {
+            Ok(0) => Err("width must be at least 1".to_string()),
+            Ok(width) => Ok(Width::Fixed(width)),
+            Err(e) => Err(e.to_string()),
+        };
+    };
+
+    if auto.is_empty() {
+        return Ok(Width::Auto(60..=120));
+    }
+
+    let range = auto
+        .strip_prefix('=')
+        .ok_or("expected auto or auto=MIN..MAX")?;
+    let (min, max) = range.split_once("..").ok_or("expected a range MIN..MAX")?;
+    let min = min
+        .parse::<usize>()
+        .map_err(|e| format!("bad minimum: {e}"))?;
+    let max = max
+        .parse::<usize>()
+        .map_err(|e| format!("bad maximum: {e}"))?;
+    if min == 0 || min > max {
+        return Err(format!("{min}..{max} is not a range of widths"));
+    }
+
+    Ok(Width::Auto(min..=max))
+}
+
+fn parse_objective(s: &str) -> Result<cargo_unfmt::Objective, String> {
+    match s {
+        "comments" => Ok(cargo_unfmt::Objective::Comments),
+        "junk" => Ok(cargo_unfmt::Objective::Junk),
+        "square" => Ok(cargo_unfmt::Objective::SQUARE),
+        _ => match s.strip_prefix("aspect=").map(str::parse::<f64>) {
+            Some(Ok(ratio)) if ratio > 0.0 && ratio.is_finite() => {
+                Ok(cargo_unfmt::Objective::Aspect(ratio))
+            }
+            Some(_) => Err("aspect ratio must be a positive number".to_string()),
+            None => Err("expected comments, junk, square, or aspect=RATIO".to_string()),
+        },
+    }
+}
+
+/// Unformat one file, reporting the scores if the width is picked
+/// automatically.
+fn unformat(
+    path: &Path,
+    src: &str,
+    config: &cargo_unfmt::Config,
+    width: &Width,
+    objective: cargo_unfmt::Objective,
+) -> anyhow::Result<Vec<u8>> {
+    let Width::Auto(widths) = width else {
+        return cargo_unfmt::unformat_with(src, config);
+    };
+
+    let search = cargo_unfmt::search_width(src, config, widths.clone(), objective)?;
+
+    let mut ranked = search.scores.clone();
+    ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    let runners_up = ranked
+        .iter()
+        .filter(|(width, _)| *width != search.width)
+        .take(3)
+        .map(|(width, score)| format!("{width} ({score:.3})"))
+        .collect::<Vec<_>>();
+    let best = ranked
+        .iter()
+        .find(|(width, _)| *width == search.width)
+        .expect("best width was scored");
+    eprintln!(
+        "[cargo-unfmt] {path:?}: width {} scored {:.3}, next best {}",
+        best.0,
+        best.1,
+        match runners_up.is_empty() {
+            true => "none".to_string(),
+            false => runners_up.join(", "),
+        }
+    );
+
+    Ok(search.unformatted)
+}
@@ -0,0 +1,244 @@
+//! Regrouping `use` declarations, which have few places to pad, so that they
+//! pack better into lines.
+
+use std::borrow::Cow;
+
+use proc_macro2::{Ident, LineColumn};
+use quote::ToTokens;
+use syn::{parse_quote, Item, ItemUse, UseTree};
+
+/// Ways to group the imports in a run of `use` declarations.
+#[derive(Debug, Clone, Copy)]
+enum Grouping {
+    /// One declaration per import, like `use a::b; use a::c;`
+    Split,
+    /// One declaration per root, like `use a::{b, c};`
+    Merge,
+}
+
+/// `src` with its `use` declarations as written, then with every other
+/// grouping that changes them.
+///
+/// Only declarations next to each other with the same attributes, visibility
+/// and leading `::` are grouped together, so `pub use` and `#[cfg]` mean the
+/// same thing afterwards.
+pub fn groupings(src: &str) -> Vec<Cow<'_, str>> {
+    let mut out = vec![Cow::Borrowed(src)];
+    let Ok(file) = syn::parse_file(src) else {
+        return out;
+    };
+
+    for grouping in [Grouping::Split, Grouping::Merge] {
+        let mut edits = vec![];
+        collect_edits(&file.items, grouping, &mut edits);
+        if !edits.is_empty() {
+            out.push(Cow::Owned(apply(src, &edits)));
+        }
+    }
+    out
+}
+
+/// A replacement for the text between two positions.
+struct Edit {
+    start: LineColumn,
+    end: LineColumn,
+    text: String,
+}
+
+/// Regroup every run of `use` declarations in `items` and the modules inside
+/// them.
+fn collect_edits(items: &[Item], grouping: Grouping, edits: &mut Vec<Edit>) {
+    let mut run = vec![];
+    for item in items {
+        if let Item::Use(item) = item {
+            run.push(item);
+            continue;
+        }
+        edits.extend(regroup_run(&run, grouping));
+        run.clear();
+
+        if let Item::Mod(syn::ItemMod {
+            content: Some((_, items)),
+            ..
+        }) = item
+        {
+            collect_edits(items, grouping, edits);
+        }
+    }
+    edits.extend(regroup_run(&run, grouping));
+}
+
+/// The edit regrouping a run of `use` declarations, if it changes anything.
+fn regroup_run(run: &[&ItemUse], grouping: Grouping) -> Option<Edit> {
+    let (first, last) = (run.first()?, run.last()?);
+
+    // Declarations can only be combined if everything but their trees match
+    let key = |item: &ItemUse| {
+        (
+            item.attrs
+                .iter()
+                .map(|attr| attr.to_token_stream().to_string())
+                .collect::<Vec<_>>(),
+            item.vis.to_token_stream().to_string(),
+            item.leading_colon.is_some(),
+        )
+    };
+    let mut keys = vec![];
+    for item in run {
+        let key = key(item);
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    let mut regrouped = vec![];
+    for k in keys {
+        let items = run.iter().filter(|item| key(item) == k).collect::<Vec<_>>();
+        let mut leaves = vec![];
+        for item in &items {
+            collect_leaves(&item.tree, &mut vec![], &mut leaves);
+        }
+        let trees = match grouping {
+            Grouping::Split => leaves
+                .into_iter()
+                .map(|(path, leaf)| chain(&path, leaf))
+                .collect(),
+            Grouping::Merge => merge(&leaves),
+        };
+        regrouped.extend(trees.into_iter().map(|tree| ItemUse {
+            tree,
+            ..(**items[0]).clone()
+        }));
+    }
+
+    let text = |items: &mut dyn Iterator<Item = &ItemUse>| {
+        items
+            .map(|item| item.to_token_stream().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let before = text(&mut run.iter().copied());
+    let after = text(&mut regrouped.iter());
+    (before != after).then(|| Edit {
+        start: first_token(first),
+        end: last_token(last),
+        text: after,
+    })
+}
+
+/// Every import in `tree`, as the path to it and the name, rename or glob at
+/// the end of the path.
+fn collect_leaves(tree: &UseTree, path: &mut Vec<Ident>, leaves: &mut Vec<(Vec<Ident>, UseTree)>) {
+    match tree {
+        UseTree::Path(tree) => {
+            path.push(tree.ident.clone());
+            collect_leaves(&tree.tree, path, leaves);
+            path.pop();
+        }
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                collect_leaves(tree, path, leaves);
+            }
+        }
+        UseTree::Name(_) | UseTree::Rename(_) | UseTree::Glob(_) => {
+            leaves.push((path.clone(), tree.clone()));
+        }
+    }
+}
+
+/// The tree importing `leaf` at the end of `path`.
+fn chain(path: &[Ident], leaf: UseTree) -> UseTree {
+    // self only works in braces
+    let mut tree = match is_self(&leaf) {
+        true => parse_quote!({ #leaf }),
+        false => leaf,
+    };
+    for ident in path.iter().rev() {
+        tree = parse_quote!(#ident::#tree);
+    }
+    tree
+}
+
+/// Trees importing every leaf, with one tree for each distinct start of a
+/// path. A name and paths through that name stay separate, so `b` and `b::c`
+/// don't become `b::{self, c}`, which would only import the module `b`.
+fn merge(leaves: &[(Vec<Ident>, UseTree)]) -> Vec<UseTree> {
+    let mut trees = vec![];
+    let mut seen = vec![];
+    for (path, leaf) in leaves {
+        let Some(first) = path.first() else {
+            trees.push(leaf.clone());
+            continue;
+        };
+        if seen.contains(&first) {
+            continue;
+        }
+        seen.push(first);
+
+        let rest = leaves
+            .iter()
+            .filter(|(path, _)| path.first() == Some(first))
+            .map(|(path, leaf)| (path[1..].to_vec(), leaf.clone()))
+            .collect::<Vec<_>>();
+        let inner = merge(&rest);
+        let tree: UseTree = match inner.as_slice() {
+            [tree] if !is_self(tree) => tree.clone(),
+            _ => parse_quote!({ #(#inner),* }),
+        };
+        trees.push(parse_quote!(#first::#tree));
+    }
+    trees
+}
+
+/// Whether `tree` imports `self`, which has to be in braces.
+fn is_self(tree: &UseTree) -> bool {
+    match tree {
+        UseTree::Name(name) => name.ident == "self",
+        UseTree::Rename(rename) => rename.ident == "self",
+        _ => false,
+    }
+}
+
+/// Start of the first token of `item`, including its attributes.
+fn first_token(item: &ItemUse) -> LineColumn {
+    let tokens = item.to_token_stream();
+    let first = tokens.into_iter().next().expect("use has tokens");
+    first.span().start()
+}
+
+/// End of the last token of `item`.
+fn last_token(item: &ItemUse) -> LineColumn {
+    let tokens = item.to_token_stream();
+    let last = tokens.into_iter().last().expect("use has tokens");
+    last.span().end()
+}
+
+/// `src` with the text of every edit replaced, where edits don't overlap and
+/// are in order.
+fn apply(src: &str, edits: &[Edit]) -> String {
+    // Byte offset of a line and column, which counts characters
+    let lines = src.split_inclusive('\n').collect::<Vec<_>>();
+    let offset = |at: LineColumn| {
+        let before = lines[..at.line - 1]
+            .iter()
+            .map(|line| line.len())
+            .sum::<usize>();
+        let line = lines.get(at.line - 1).copied().unwrap_or("");
+        let column = line
+            .char_indices()
+            .nth(at.column)
+            .map_or(line.len(), |(i, _)| i);
+        before + column
+    };
+
+    let mut out = String::new();
+    let mut pos = 0;
+    for edit in edits {
+        let start = offset(edit.start);
+        out.push_str(&src[pos..start]);
+        out.push_str(&edit.text);
+        pos = offset(edit.end);
+    }
+    out.push_str(&src[pos..]);
+    out
+}
@@ -0,0 +1,96 @@
+//! Reordering top-level items so that they pack into lines with less filler.
+
+use crate::{
+    ir::{Ir, RichToken, FRAME_SIDE},
+    Config,
+};
+
+/// `ir` with its top-level items reordered to pack into lines of
+/// `config.width`, if that changes the order.
+///
+/// Each run of items between pinned ones is packed greedily, like bins: the
+/// next item is the longest that still fits on the current line, or the
+/// longest of all if none fit. Pinned items, and anything before the first
+/// item such as inner attributes, stay where they are.
+pub fn pack<'a>(ir: &Ir<'a>, config: &Config) -> Option<Ir<'a>> {
+    let width = match config.frame {
+        true => config.width.saturating_sub(2 * FRAME_SIDE.len()),
+        false => config.width,
+    }
+    .max(1);
+
+    let tokens = ir.tokens();
+    let starts = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| matches!(token, RichToken::Boundary { .. }))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let prefix = &tokens[..starts.first().copied().unwrap_or(tokens.len())];
+    let items = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| &tokens[start..starts.get(i + 1).copied().unwrap_or(tokens.len())])
+        .collect::<Vec<_>>();
+    let lens = items
+        .iter()
+        .map(|item| unpadded_len(item))
+        .collect::<Vec<_>>();
+
+    let mut column = unpadded_len(prefix) % width;
+    let mut order = vec![];
+    let mut left = vec![];
+    for (i, item) in items.iter().enumerate() {
+        let pinned = matches!(item[0], RichToken::Boundary { pinned: true });
+        if !pinned {
+            left.push(i);
+            continue;
+        }
+        pack_run(&mut left, &lens, width, &mut column, &mut order);
+        column = (column + lens[i]) % width;
+        order.push(i);
+    }
+    pack_run(&mut left, &lens, width, &mut column, &mut order);
+
+    if order.iter().copied().eq(0..items.len()) {
+        return None;
+    }
+    Some(
+        prefix
+            .iter()
+            .chain(order.into_iter().flat_map(|i| items[i]))
+            .cloned()
+            .collect(),
+    )
+}
+
+/// Move the items in `run` to the end of `order`, packing them into lines
+/// starting at `column`.
+fn pack_run(
+    run: &mut Vec<usize>,
+    lens: &[usize],
+    width: usize,
+    column: &mut usize,
+    order: &mut Vec<usize>,
+) {
+    while !run.is_empty() {
+        let room = width - *column;
+        // Earlier items win ties, so equal items keep their order
+        let longest = |fits: bool| {
+            (0..run.len())
+                .filter(|&j| !fits || lens[run[j]] <= room)
+                .max_by_key(|&j| (lens[run[j]], std::cmp::Reverse(j)))
+        };
+        let j = longest(true)
+            .or_else(|| longest(false))
+            .expect("run isn't empty");
+        let i = run.remove(j);
+        *column = (*column + lens[i]) % width;
+        order.push(i);
+    }
+}
+
+/// Total length of `tokens` before padding.
+fn unpadded_len(tokens: &[RichToken]) -> usize {
+    tokens.iter().map(RichToken::len).sum()
+}
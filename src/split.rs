@@ -0,0 +1,229 @@
+//! Splitting string literals that don't fit on a line.
+
+use std::collections::VecDeque;
+
+use crate::SafeLen;
+
+/// Part of the contents of a string literal.
+#[derive(Debug, Clone)]
+enum Unit {
+    /// An ASCII character as written, which could be escaped instead
+    Char(char),
+    /// An escape, or a character that can't be escaped
+    Fixed(String),
+}
+
+impl Unit {
+    fn len(&self) -> usize {
+        match self {
+            Unit::Char(_) => 1,
+            Unit::Fixed(text) => text.as_str().safe_len(),
+        }
+    }
+
+    fn as_str(&self) -> String {
+        match self {
+            Unit::Char(c) => c.to_string(),
+            Unit::Fixed(text) => text.clone(),
+        }
+    }
+}
+
+/// Split a string or byte string literal into pieces joined by `\`-newline
+/// continuations. The first piece is exactly `first` characters long and the
+/// ones after it exactly `width`, except for the last which is shorter.
+/// Characters are escaped to make the lengths come out exact, and newlines in
+/// the literal are escaped so that every piece is a single line.
+///
+/// Returns `None` if the literal isn't a string or byte string, or there isn't
+/// enough room to split it.
+pub fn split_string(literal: &str, first: usize, width: usize) -> Option<Vec<String>> {
+    let (prefix, bytes) = match literal {
+        _ if literal.starts_with('"') => ("\"", false),
+        _ if literal.starts_with("b\"") => ("b\"", true),
+        _ => return None,
+    };
+    let body = literal.strip_prefix(prefix)?.strip_suffix('"')?;
+    let mut units = units(body);
+
+    let mut pieces = vec![];
+    let mut piece = prefix.to_string();
+    let mut avail = first;
+    loop {
+        // Continuations skip leading whitespace, so it has to be escaped
+        if !pieces.is_empty() {
+            if let Some(Unit::Char(c @ ' ')) = units.front() {
+                *units.front_mut().unwrap() = Unit::Fixed(escape(*c, 3));
+            }
+        }
+
+        let rest = piece.as_str().safe_len() + units.iter().map(Unit::len).sum::<usize>() + 1;
+        if rest < avail || (pieces.is_empty() && rest == avail) {
+            piece.extend(units.iter().map(Unit::as_str));
+            piece.push('"');
+            pieces.push(piece);
+            return Some(pieces);
+        }
+
+        // Leave room for the backslash, and something for the last piece
+        let budget = avail.checked_sub(1)?;
+        let mut len = piece.as_str().safe_len();
+        if len > budget {
+            return None;
+        }
+        let mut taken = vec![];
+        while units.len() > 1 && len + units[0].len() <= budget {
+            let unit = units.pop_front().unwrap();
+            len += unit.len();
+            taken.push(unit);
+        }
+
+        // Give back characters until escapes can make up the difference
+        loop {
+            let chars = taken
+                .iter()
+                .filter(|unit| matches!(unit, Unit::Char(_)))
+                .count();
+            if reachable(budget - len, chars, bytes) {
+                break;
+            }
+            let unit = taken.pop()?;
+            len -= unit.len();
+            units.push_front(unit);
+        }
+        if taken.is_empty() {
+            return None;
+        }
+
+        let mut extra = budget - len;
+        for unit in taken.iter_mut().rev() {
+            if extra == 0 {
+                break;
+            }
+            if let Unit::Char(c) = unit {
+                let n = escape_len(extra, bytes);
+                *unit = Unit::Fixed(escape(*c, n));
+                extra -= n;
+            }
+        }
+
+        piece.extend(taken.iter().map(Unit::as_str));
+        piece.push('\\');
+        pieces.push(std::mem::take(&mut piece));
+        avail = width;
+    }
+}
+
+/// Split a raw string literal into raw strings shorter than `width`, with
+/// newlines as separate `"\n"` pieces, to be joined with `concat!`. Every
+/// piece but the last ends in a comma.
+///
+/// Returns `None` if the literal isn't a raw string, or `width` is too small
+/// for any of its contents.
+pub fn split_raw(literal: &str, width: usize) -> Option<Vec<String>> {
+    let rest = literal.strip_prefix('r')?;
+    let hashes = &rest[..rest.find('"')?];
+    if hashes.chars().any(|c| c != '#') {
+        return None;
+    }
+    let open = format!("r{hashes}\"");
+    let close = format!("\"{hashes}");
+    let body = literal.strip_prefix(&open)?.strip_suffix(&close)?;
+
+    // r#"..."#, needs to fit on a line with at least one character inside
+    let room = width.checked_sub(open.len() + close.len() + 2)?;
+    if room == 0 {
+        return None;
+    }
+
+    let mut pieces = vec![];
+    for (i, line) in body.split('\n').enumerate() {
+        if i != 0 {
+            pieces.push("\"\\n\"".to_string());
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let chars = line.chars().collect::<Vec<_>>();
+        for chunk in chars.chunks(room) {
+            pieces.push(format!("{open}{}{close}", chunk.iter().collect::<String>()));
+        }
+    }
+    if pieces.is_empty() {
+        pieces.push(format!("{open}{close}"));
+    }
+
+    let last = pieces.len() - 1;
+    for piece in &mut pieces[..last] {
+        piece.push(',');
+    }
+    Some(pieces)
+}
+
+fn units(body: &str) -> VecDeque<Unit> {
+    let mut units = VecDeque::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        let unit = match c {
+            '\\' => match chars.next() {
+                // Existing continuations don't add anything
+                Some('\n' | '\r') => {
+                    while chars
+                        .next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+                        .is_some()
+                    {}
+                    continue;
+                }
+                Some('x') => {
+                    Unit::Fixed(format!("\\x{}", chars.by_ref().take(2).collect::<String>()))
+                }
+                Some('u') => {
+                    let mut escape = "\\u".to_string();
+                    for c in chars.by_ref() {
+                        escape.push(c);
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                    Unit::Fixed(escape)
+                }
+                Some(c) => Unit::Fixed(format!("\\{c}")),
+                None => break,
+            },
+            // Source line endings are normalized to \n
+            '\r' if chars.peek() == Some(&'\n') => continue,
+            '\n' => Unit::Fixed("\\n".to_string()),
+            '\r' => Unit::Fixed("\\r".to_string()),
+            '\t' => Unit::Fixed("\\t".to_string()),
+            c if c.is_ascii() => Unit::Char(c),
+            c => Unit::Fixed(c.to_string()),
+        };
+        units.push_back(unit);
+    }
+    units
+}
+
+/// Whether escaping at most `chars` characters can add exactly `extra`
+/// characters. `\x41` adds 3, and in strings `\u{41}` through `\u{000041}` add
+/// 5 to 9.
+fn reachable(extra: usize, chars: usize, bytes: bool) -> bool {
+    match bytes {
+        true => extra % 3 == 0 && extra / 3 <= chars,
+        false => extra == 0 || (extra == 3 && chars >= 1) || (5..=9 * chars).contains(&extra),
+    }
+}
+
+/// Characters one escape should add, leaving a reachable remainder.
+fn escape_len(extra: usize, bytes: bool) -> usize {
+    if bytes || extra == 3 {
+        return 3;
+    }
+    let escapes = extra.div_ceil(9);
+    (extra - 5 * (escapes - 1)).min(9)
+}
+
+/// Escape an ASCII character so that it's `1 + extra` characters long.
+fn escape(c: char, extra: usize) -> String {
+    match extra {
+        3 => format!("\\x{:02x}", c as u32),
+        _ => format!("\\u{{{:0width$x}}}", c as u32, width = extra - 3),
+    }
+}
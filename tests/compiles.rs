@@ -0,0 +1,226 @@
+//! Unformatted code has to type check, and do the same thing, in every
+//! edition. Each file in `tests/corpus` is unformatted at several widths and
+//! with several options and checked by rustc. Files with a `main` are also run
+//! and have to print what the original does.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use cargo_unfmt::{Config, Overflow};
+
+const WIDTHS: [usize; 6] = [20, 33, 47, 61, 75, 100];
+const EDITIONS: [&str; 2] = ["2021", "2024"];
+
+/// Options that every file is checked with.
+fn configs() -> Vec<(&'static str, Config)> {
+    vec![
+        ("default", Config::default()),
+        (
+            "spread",
+            Config {
+                spread: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "max-junk-0",
+            Config {
+                max_junk: Some(0),
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+fn check(name: &str) {
+    check_with(name, &configs());
+}
+
+/// Check `name` with each of `configs`, which are named for the flags they
+/// stand for.
+fn check_with(name: &str, configs: &[(&str, Config)]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/corpus")
+        .join(name);
+    let src = fs::read_to_string(&path).unwrap();
+    // Several tests can check the same file at once
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join(name.trim_end_matches(".rs"))
+        .join(configs[0].0);
+    fs::create_dir_all(&dir).unwrap();
+
+    let runs = src.contains("fn main()");
+    let expected = runs.then(|| run(&path, &dir.join("original")));
+
+    for width in WIDTHS {
+        for (flags, config) in configs {
+            let config = Config {
+                width,
+                ..config.clone()
+            };
+            let unformatted = cargo_unfmt::unformat_with(&src, &config).unwrap();
+            let out = dir.join(format!("{width}-{flags}.rs"));
+            fs::write(&out, unformatted).unwrap();
+
+            for edition in EDITIONS {
+                // Blocks around values are only there to hold junk, and rustc
+                // warns about ones that don't
+                let output = Command::new("rustc")
+                    .args(["--edition", edition, "--crate-type", "lib"])
+                    .args([
+                        "--emit=metadata",
+                        "-A",
+                        "warnings",
+                        "-D",
+                        "unused_braces",
+                        "-o",
+                    ])
+                    .arg(dir.join("out.rmeta"))
+                    .arg(&out)
+                    .output()
+                    .unwrap();
+                assert!(
+                    output.status.success(),
+                    "{} doesn't compile in edition {edition}:\n{}",
+                    out.display(),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+
+            if let Some(expected) = &expected {
+                let actual = run(&out, &dir.join(format!("{width}-{flags}")));
+                assert_eq!(&actual, expected, "{} prints something else", out.display());
+            }
+        }
+    }
+}
+
+/// Build `src` in the latest edition and run it, returning what it prints.
+fn run(src: &Path, exe: &Path) -> String {
+    let edition = EDITIONS[EDITIONS.len() - 1];
+    let output = Command::new("rustc")
+        .args(["--edition", edition, "-A", "warnings", "-o"])
+        .arg(exe)
+        .arg(src)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} doesn't build:\n{}",
+        src.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = Command::new(exe).output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn doc_comments() {
+    check("doc_comments.rs");
+}
+
+#[test]
+fn destructuring() {
+    check("destructuring.rs");
+}
+
+#[test]
+fn temporaries() {
+    check("temporaries.rs");
+}
+
+#[test]
+fn frame() {
+    let frame = Config {
+        frame: true,
+        ..Default::default()
+    };
+    check_with("temporaries.rs", &[("frame", frame)]);
+}
+
+#[test]
+fn acrostic() {
+    let acrostic = Config {
+        acrostic: Some("HELLO WORLD".to_string()),
+        ..Default::default()
+    };
+    check_with("temporaries.rs", &[("acrostic", acrostic)]);
+}
+
+#[test]
+fn split() {
+    let split = Config {
+        overflow: Overflow::Split,
+        ..Default::default()
+    };
+    check_with("literals.rs", &[("split", split)]);
+}
+
+#[test]
+fn multi_line() {
+    check("multi_line.rs");
+}
+
+#[test]
+fn types() {
+    check("types.rs");
+}
+
+#[test]
+fn patterns() {
+    check("patterns.rs");
+}
+
+#[test]
+fn items() {
+    check("items.rs");
+}
+
+#[test]
+fn data() {
+    check("data.rs");
+}
+
+#[test]
+fn builder() {
+    check("builder.rs");
+}
+
+#[test]
+fn identifiers() {
+    check("identifiers.rs");
+}
+
+#[test]
+fn regroup() {
+    let regroup = Config {
+        regroup: true,
+        ..Default::default()
+    };
+    check_with("imports.rs", &[("regroup", regroup)]);
+}
+
+#[test]
+fn reorder() {
+    let reorder = Config {
+        reorder: true,
+        ..Default::default()
+    };
+    check_with("reordered.rs", &[("reorder", reorder)]);
+}
+
+#[test]
+fn std_macros() {
+    check("std_macros.rs");
+}
+
+#[test]
+fn macro_dsl() {
+    check("macro_dsl.rs");
+}
+
+#[test]
+fn panics() {
+    check("panics.rs");
+}
@@ -0,0 +1,57 @@
+#[derive(Debug, Default)]
+struct Request {
+    url: String,
+    retries: u8,
+    verbose: bool,
+}
+
+struct Builder {
+    request: Request,
+}
+
+impl Builder {
+    fn new(url: &str) -> Self {
+        Builder {
+            request: Request {
+                url: url.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn retries(mut self, retries: u8) -> Self {
+        self.request.retries = retries;
+        self
+    }
+
+    fn verbose(mut self) -> Self {
+        self.request.verbose = true;
+        self
+    }
+
+    fn noop(self) -> Self {
+        self
+    }
+
+    fn build(self) -> Request {
+        self.request
+    }
+}
+
+fn nothing() {}
+
+fn main() {
+    nothing();
+    let hook = || {};
+    hook();
+    let request = Builder::new("example.com").retries(3).noop().verbose().build();
+    let attempts = if request.verbose { request.retries } else { 0 };
+    let mut count = 0;
+    while count < attempts {
+        count += 1;
+    }
+    loop {
+        break;
+    }
+    println!("{request:?} {count}");
+}
@@ -0,0 +1,34 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+    Custom(u8, u8, u8),
+}
+
+#[derive(Debug)]
+struct Pixel {
+    x: u16,
+    y: u16,
+    color: Color,
+    alpha: u8,
+}
+
+struct Palette(Color, Color, Color);
+
+fn brightness(color: Color, scale: u16) -> u16 {
+    match color {
+        Color::Red => 76 * scale,
+        Color::Green => 150 * scale,
+        Color::Blue => 29 * scale,
+        Color::Custom(r, g, b) => (r as u16 + g as u16 + b as u16) * scale / 3,
+    }
+}
+
+fn main() {
+    let palette = Palette(Color::Red, Color::Custom(10, 20, 30), Color::Blue);
+    let pixel = Pixel { x: 1, y: 2, color: palette.1, alpha: 255 };
+    println!("{pixel:?}");
+    println!("{}", brightness(palette.0, 2) + brightness(pixel.color, 3));
+    println!("{}", palette.2 == Color::Green);
+}
@@ -0,0 +1,16 @@
+struct P { x: i32, y: i32 }
+struct T(i32, i32);
+fn main() {
+    let (mut a, mut b, mut f, mut g);
+    let mut arr = [0; 3];
+    (f, ..) = (8, 9, 10);
+    (a, _, b) = (1, 2, 3);
+    [arr[0], .., g] = [4, 5, 6, 7];
+    P { x: a, y: b } = P { x: a + 1, y: b * 2 };
+    let (x, y);
+    P { x, y } = P { x: 11, y: 12 };
+    T(f, _) = T(f + 1, 0);
+    ((a, b), g) = ((b, a), g + 1);
+    let v: [u8; _] = [1, 2];
+    println!("{a} {b} {f} {g} {x} {y} {:?} {:?}", arr, v);
+}
@@ -0,0 +1,19 @@
+pub struct S { /// d
+pub b: u8 }
+
+pub struct T {
+    pub a: u8, /// next
+    pub b: u8,
+}
+
+pub struct U {
+    /** d */ pub b: u8,
+}
+
+pub enum E { /// d
+A }
+
+pub fn f(e: E) -> u8 {
+    match e { /// d
+    E::A => 1 }
+}
@@ -0,0 +1,46 @@
+use std::fmt::Write as _;
+
+mod shapes {
+    pub struct Square<'a> {
+        pub name: &'a str,
+        pub side: u32,
+    }
+
+    impl<'a> Square<'a> {
+        pub fn new(name: &'a str, side: u32) -> Self {
+            Self { name, side }
+        }
+
+        pub fn area(&self) -> u32 {
+            super::square(self.side)
+        }
+    }
+}
+
+fn square(n: u32) -> u32 {
+    n * n
+}
+
+macro_rules! twice {
+    ($e:expr) => {
+        $e + $e
+    };
+}
+
+fn r#gen(r#try: u32) -> u32 {
+    twice!(r#try)
+}
+
+fn main() {
+    let shape = crate::shapes::Square::new("box", 3);
+    let mut out = String::new();
+    let _ = write!(out, "{} {}", shape.name, shape.area());
+    let total = 'outer: loop {
+        for i in 0.. {
+            if i > 2 {
+                break 'outer r#gen(i);
+            }
+        }
+    };
+    println!("{out} {total}");
+}
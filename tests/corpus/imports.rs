@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display};
+use std::fmt::Write as _;
+
+mod names {
+    pub use std::cmp::max;
+    pub use std::cmp::min;
+    #[cfg(test)]
+    use std::cmp::Ordering;
+    #[cfg(not(test))]
+    pub use std::cmp::Ordering;
+}
+
+use names::{max, min, Ordering};
+
+struct Pair(u32, u32);
+
+impl Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", min(self.0, self.1), max(self.0, self.1))
+    }
+}
+
+fn main() {
+    let mut counts = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut sorted = BTreeMap::new();
+    for word in ["b", "a", "b", "c"] {
+        *counts.entry(word).or_insert(0) += 1;
+        seen.insert(word);
+        sorted.insert(word, word.len());
+    }
+    let mut out = String::new();
+    let _ = write!(out, "{} {} {:?}", Pair(3, 1), seen.len(), sorted);
+    println!("{out} {} {:?}", counts["b"], 1.cmp(&2) == Ordering::Less);
+}
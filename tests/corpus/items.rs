@@ -0,0 +1,67 @@
+use std::fmt;
+
+mod shapes {
+    pub struct Square(pub u32);
+    pub struct Circle(pub u32);
+
+    impl Square {
+        pub fn area(&self) -> u32 {
+            self.0 * self.0
+        }
+    }
+
+    impl Circle {
+        pub fn area(&self) -> u32 {
+            3 * self.0 * self.0
+        }
+    }
+}
+
+use shapes::{Circle, Square};
+
+trait Named {
+    const NAME: &'static str;
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+impl Named for Square {
+    const NAME: &'static str = "square";
+}
+
+impl Named for Circle {
+    const NAME: &'static str = "circle";
+}
+
+struct Unit;
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unit")
+    }
+}
+
+unsafe extern "C" {
+    fn abs(input: i32) -> i32;
+}
+
+enum Kind {
+    A,
+    B,
+}
+
+static COUNT: u32 = 2;
+
+type Pair = (u32, u32);
+
+fn pair() -> Pair {
+    (COUNT, COUNT + 1)
+}
+
+fn main() {
+    println!("{} {}", Square(2).name(), Square(2).area());
+    println!("{} {}", Circle(1).name(), Circle(1).area());
+    println!("{Unit} {:?} {}", pair(), unsafe { abs(-4) });
+    println!("{}", matches!(Kind::A, Kind::B));
+}
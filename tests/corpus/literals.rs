@@ -0,0 +1,31 @@
+macro_rules! which {
+    ("a matcher literal that is longer than the narrowest width") => {
+        1
+    };
+    ($other:expr) => {
+        2
+    };
+}
+
+#[doc = "an attribute literal that is longer than the narrowest width"]
+fn describe(n: u32) -> &'static str {
+    match n {
+        0 => "a pattern literal that is longer than the narrowest width",
+        _ => "an expression literal that is longer than the narrowest width",
+    }
+}
+
+fn main() {
+    println!(
+        "{}",
+        which!("a matcher literal that is longer than the narrowest width")
+    );
+    println!(
+        "{}",
+        stringify!("a stringified literal that is longer than the narrowest width")
+    );
+    println!("{}", describe(0));
+    println!("{}", describe(1));
+    let bytes = b"a byte string literal that is longer than the narrowest width";
+    println!("{}", bytes.len());
+}
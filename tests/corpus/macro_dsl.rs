@@ -0,0 +1,38 @@
+macro_rules! sum {
+    () => { 0 };
+    ($x:expr $(, $rest:expr)*) => { $x + sum!($($rest),*) };
+}
+
+macro_rules! assign {
+    ($($name:ident)+ = $value:expr) => { [$(stringify!($name)),+].len() as u32 * $value };
+    ($name:ident + = $value:expr) => { $value + 1 };
+}
+
+macro_rules! compare {
+    ($a:literal < < $b:literal) => { ($a << $b) - 1 };
+    ($a:literal > => $b:literal) => { if $a > $b { $a } else { $b } };
+}
+
+macro_rules! pick {
+    ($cond:expr => $yes:expr ; $no:expr) => {
+        match $cond {
+            true => $yes * 2,
+            false => $no + 3,
+        }
+    };
+}
+
+macro_rules! table {
+    ($($key:ident : $value:expr),* $(,)?) => {
+        vec![$((stringify!($key), $value)),*]
+    };
+}
+
+fn main() {
+    let total = sum!(1, 2, 3, 4);
+    let assigned = assign!(a b c = 5) + assign!(x + = 9);
+    let shifted = compare!(1 < < 4) + compare!(3 > => 7);
+    let picked = pick!(total > 5 => total ; assigned);
+    let rows = table!(left: total, right: shifted,);
+    println!("{total} {assigned} {shifted} {picked} {rows:?}");
+}
@@ -0,0 +1,20 @@
+const POEM: &str = "roses are red,
+    violets are blue,
+this literal spans
+several lines";
+
+fn raw() -> &'static str {
+    r#"a raw "string"
+that also
+    spans lines"#
+}
+
+fn main() {
+    let bytes = b"bytes
+on two lines";
+    println!("{POEM}");
+    println!("{}", raw());
+    println!("{}", bytes.len());
+    println!("{}", "one more
+literal".to_uppercase());
+}
@@ -0,0 +1,20 @@
+use std::panic;
+
+fn message(check: impl FnOnce() + panic::UnwindSafe) -> String {
+    let payload = panic::catch_unwind(check).unwrap_err();
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast::<&str>().unwrap().to_string(),
+    }
+}
+
+fn main() {
+    // assert! prints its condition as written, so it has to stay that way
+    panic::set_hook(Box::new(|_| {}));
+    let value: i32 = 3;
+    println!("{}", message(|| assert!(value == 4)));
+    println!("{}", message(|| assert!(value + 1 > 2 * 5 && value != 3)));
+    println!("{}", message(|| debug_assert!(value.pow(2) == 10)));
+    println!("{}", message(|| assert!(value == 4, "{} isn't {}", value, 4)));
+    println!("{}", message(|| assert_eq!(value, 4)));
+}
@@ -0,0 +1,45 @@
+#[derive(Debug)]
+enum State {
+    Idle,
+    Running(u8, u8),
+    Done { code: u8, last: bool },
+}
+
+const limit: u8 = 9;
+
+trait Step {
+    fn step(self, input: Option<u8>) -> Self;
+}
+
+unsafe extern "C" {
+    fn abs(input: i32) -> i32;
+}
+
+impl Step for State {
+    fn step(self, input: Option<u8>) -> State {
+        match (self, input) {
+            (State::Idle, Some(limit)) => State::Done { code: limit, last: false },
+            (State::Idle, Some(first)) => State::Running(first, first),
+            (State::Running(low, high), Some(next)) => State::Running(low.min(next), high.max(next)),
+            (State::Running(low, high), None) => State::Done { code: high - low, last: true },
+            (done, _) => done,
+        }
+    }
+}
+
+fn main() {
+    let inputs = [Some(4), Some(7), Some(2), None, Some(1)];
+    let mut state = State::Idle;
+    for (i, input) in inputs.into_iter().enumerate() {
+        state = state.step(input);
+        println!("{i}: {state:?}");
+    }
+    let State::Done { code, last } = state else {
+        return;
+    };
+    let [first, .., end] = [code, 1, 2];
+    let add = |a: u8, (b, c): (u8, u8)| a + b + c;
+    println!("{} {last}", add(first, (end, 0)));
+    println!("{:?}", State::Idle.step(Some(limit)));
+    println!("{}", unsafe { abs(-2) });
+}
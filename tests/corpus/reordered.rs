@@ -0,0 +1,42 @@
+#![allow(dead_code)]
+
+const LIMIT: u32 = 10;
+static GREETING: &str = "hello";
+struct Counter { count: u32 }
+type Count = u32;
+
+macro_rules! square {
+    ($n:expr) => {
+        $n * $n
+    };
+}
+
+fn area(side: u32) -> u32 { square!(side) }
+fn volume(side: u32) -> u32 { square!(side) * side }
+impl Counter { fn bump(&mut self) -> Count { self.count += 1; self.count } }
+enum Shape { Square(u32), Cube(u32) }
+trait Measure { fn measure(&self) -> u32; }
+
+macro_rules! describe {
+    ($shape:expr) => {
+        format!("{} {}", GREETING, $shape.measure())
+    };
+}
+
+impl Measure for Shape {
+    fn measure(&self) -> u32 {
+        match self {
+            Shape::Square(side) => area(*side),
+            Shape::Cube(side) => volume(*side),
+        }
+    }
+}
+
+fn main() {
+    let mut counter = Counter { count: 0 };
+    for shape in [Shape::Square(3), Shape::Cube(2)] {
+        if counter.bump() < LIMIT {
+            println!("{}", describe!(shape));
+        }
+    }
+}
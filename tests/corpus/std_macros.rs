@@ -0,0 +1,31 @@
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq)]
+enum Level {
+    Low,
+    High(u8),
+}
+
+fn level(n: u8) -> Level {
+    match n {
+        0..=9 => Level::Low,
+        n => Level::High(n),
+    }
+}
+
+fn main() {
+    let values = vec![3u8, 14, 15, 92];
+    let zeros = vec![0u8; 3];
+    let total: u32 = values.iter().map(|&v| u32::from(v)).sum();
+    assert!(total > 100, "total was {}", total);
+    assert_eq!(zeros.len(), 3);
+    assert_ne!(level(2), level(20), "{:?} and {:?}", level(2), level(20));
+    debug_assert!(matches!(level(50), Level::High(n) if n > 40));
+    let mut out = String::new();
+    write!(out, "{total}-{}", values.len() * 2).unwrap();
+    writeln!(out, " {first:>4}", first = values[0] + 1).unwrap();
+    let label = format!("{:?}/{:?}", level(values[1]), level(values[0]));
+    print!("{out}");
+    println!("{label} {}", matches!(level(7), Level::Low));
+    eprintln!("{}", dbg!(total + 1));
+}
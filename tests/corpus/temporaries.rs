@@ -0,0 +1,39 @@
+struct Pair<'a> {
+    name: &'a str,
+    len: usize,
+}
+
+fn take(s: &str) -> usize {
+    s.len()
+}
+
+fn first(v: &[i32]) -> &i32 {
+    &v[0]
+}
+
+fn cast_ref(value: &dyn std::fmt::Debug) -> String {
+    format!("{value:?}")
+}
+
+fn describe(pair: Pair) -> String {
+    format!("{}:{}", pair.name, pair.len)
+}
+
+fn main() {
+    let v = vec![3, 4];
+    let s = String::from("place");
+    println!("{}", take(String::from("hello").as_str()));
+    println!("{}", take(&String::from("world")));
+    println!("{}", first(&vec![1, 2]));
+    println!("{}", first(vec![5, 6].as_slice()) + first(&v));
+    println!("{}", describe(Pair { name: &String::from("x"), len: s.len() + 1 }));
+    println!("{}", describe(Pair { name: &s, len: take(&s) * 2 }));
+    println!("{}", cast_ref(&s.len() as &dyn std::fmt::Debug));
+    println!("{}", cast_ref(&(v[0] as u8) as &dyn std::fmt::Debug));
+    let lens: Vec<usize> = ["a", "bb"].iter().map(|x| take(&x.to_string())).collect();
+    println!("{lens:?}");
+    match take(s.clone().trim()) {
+        5 => println!("{}", Some(take(&s)).unwrap()),
+        n => println!("{n}"),
+    }
+}
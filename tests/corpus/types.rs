@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+trait Store<K, V> {
+    type Iter<'a>: Iterator<Item = (&'a K, &'a V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    fn get(&self, key: &K) -> Option<&V>;
+    fn iter(&self) -> Self::Iter<'_>;
+}
+
+impl<K: std::hash::Hash + Eq, V> Store<K, V> for HashMap<K, V> {
+    type Iter<'a> = std::collections::hash_map::Iter<'a, K, V> where K: 'a, V: 'a;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMap::iter(self)
+    }
+}
+
+unsafe extern "C" {
+    fn abs(input: i32) -> i32;
+}
+
+type Callback = fn(u8, u8) -> u8;
+type Boxed<'a> = Box<dyn Fn(&str) -> Cow<'a, str> + 'a>;
+
+struct Buffer<const N: usize>(pub [u8; N]);
+
+const WIDTH: usize = 2;
+
+impl<const N: usize> Buffer<N> {
+    fn widen(self) -> Buffer<WIDTH> {
+        Buffer([self.0[0]; WIDTH])
+    }
+}
+
+fn nested(values: &&Vec<Vec<u8>>) -> <Vec<u8> as IntoIterator>::Item {
+    let mut total: u8 = 0;
+    total >>= 1;
+    values.iter().map(|inner: &Vec<u8>| inner.len() as u8).sum::<u8>() + total
+}
+
+fn shown(value: impl std::fmt::Display) -> String {
+    value.to_string()
+}
+
+fn never() -> ! {
+    std::process::exit(0)
+}
+
+fn apply<S: Store<u8, String>>(store: &S, keys: Vec<u8>, f: Callback) -> Vec<(u8, bool)> {
+    keys.into_iter()
+        .map(|key| (f(key, 1), store.get(&key).is_some()))
+        .collect::<Vec<_>>()
+}
+
+fn main() {
+    let mut store: HashMap<u8, String> = HashMap::new();
+    store.insert(2, String::from("two"));
+    let f: Callback = |a, b| a + b;
+    println!("{:?}", apply(&store, vec![1, 2, 3], f));
+    println!("{}", Store::iter(&store).count());
+    let upper: Boxed = Box::new(|s| Cow::Owned(s.to_uppercase()));
+    println!("{}", upper("loud"));
+    println!("{}", unsafe { abs(-3) });
+    println!("{:?}", Buffer::<1>([7]).widen().0);
+    println!("{}", nested(&&vec![vec![1, 2], vec![3]]));
+    println!("{}", shown(1.5f32 as f64));
+    never()
+}
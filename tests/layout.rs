@@ -0,0 +1,1462 @@
+//! Unformatted code has to come out in the shape that was asked for. Each
+//! test checks what an option promises about the lines it makes.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use cargo_unfmt::{Config, Objective, Overflow};
+use quote::ToTokens;
+use syn::ext::IdentExt;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+
+fn unformat(src: &str, config: &Config) -> String {
+    String::from_utf8(cargo_unfmt::unformat_with(src, config).unwrap()).unwrap()
+}
+
+/// Check that every line is `width` characters wide.
+fn assert_width(lines: &[&str], width: usize) {
+    for line in lines {
+        assert_eq!(line.chars().count(), width, "{line:?} is the wrong width");
+    }
+}
+
+/// The widths that tests looking for a kind of padding try, since it depends
+/// on the width whether it's used at all.
+const WIDTHS: std::ops::RangeInclusive<usize> = 20..=80;
+
+/// Unformat `src` with `config` at each of [`WIDTHS`], and count the widths
+/// where `check` finds what it's looking for in the output.
+fn count_widths(src: &str, config: &Config, mut check: impl FnMut(usize, &str) -> bool) -> usize {
+    WIDTHS
+        .filter(|&width| {
+            let config = Config {
+                width,
+                ..config.clone()
+            };
+            check(width, &unformat(src, &config))
+        })
+        .count()
+}
+
+/// Unformat `src` with nothing but optional text and spelling to pad it,
+/// `by` characters wider than it is.
+fn short(src: &str, by: usize) -> String {
+    let config = Config {
+        width: src.len() + by,
+        max_parens: Some(0),
+        max_junk: Some(0),
+        ..Default::default()
+    };
+    unformat(src, &config)
+}
+
+fn one_short(src: &str) -> String {
+    short(src, 1)
+}
+
+#[test]
+fn odd_leftovers() {
+    assert_eq!(one_short("fn f(){g(1)}"), "fn f(){g(1,)}\n");
+    assert_eq!(one_short("fn f(){let _=[1];}"), "fn f(){let _=[1,];}\n");
+    assert_eq!(
+        one_short("fn main(){let _=(1,2);}"),
+        "fn main(){let _=(1,2,);}\n"
+    );
+    assert_eq!(
+        one_short("fn f(){match 1{_=>()}}"),
+        "fn f(){match 1{_=>(),}}\n"
+    );
+    assert_eq!(one_short("fn f<T:Clone>(){}"), "fn f<T:Clone+>(){}\n");
+    assert_eq!(one_short("type T=(u8,u8);"), "type T=(u8,u8,);\n");
+    assert_eq!(one_short("type T=Vec<u8>;"), "type T=Vec<u8,>;\n");
+    assert_eq!(one_short("type F=fn(u8)->u8;"), "type F=fn(u8,)->u8;\n");
+    assert_eq!(
+        one_short("type G=Box<dyn Fn(u8)>;"),
+        "type G=Box<dyn Fn(u8,)>;\n"
+    );
+    assert_eq!(
+        one_short("fn f(){if let Some(v)=None::<u8>{}}"),
+        "fn f(){if let Some(v,)=None::<u8>{}}\n"
+    );
+    assert_eq!(
+        one_short("struct P{x:u8}fn f(P{x:y}:P){}"),
+        "struct P{x:u8}fn f(P{x:y,}:P){}\n"
+    );
+}
+
+#[test]
+fn signatures() {
+    assert_eq!(short("fn f(){}", 4), "fn r#f<>(){}\n");
+    assert_eq!(short("fn f(){}", 13), "pub(self)fn r#f<>(){}\n");
+    assert_eq!(short("fn f(){}", 20), "pub(in self)fn r#f<>()->(){}\n");
+    assert_eq!(short("fn f(){}", 25), "pub(in self)fn r#f<>()->()where{}\n");
+    assert_eq!(
+        short("trait T{fn f(&self)->u8;}", 14),
+        "trait r#T{fn r#f<>(&self)->r#u8 where;}\n"
+    );
+}
+
+/// Unformat `src` with junk but no parentheses to pad it, `by` characters
+/// wider than it is. One junk statement in a row makes each slot take a turn.
+fn junk_short(src: &str, by: usize) -> String {
+    let config = Config {
+        width: src.len() + by,
+        max_parens: Some(0),
+        max_junk: Some(1),
+        ..Default::default()
+    };
+    unformat(src, &config)
+}
+
+#[test]
+fn tails_and_empty_blocks() {
+    assert_eq!(junk_short("fn f(){}", 2), "fn f(){3;}\n");
+    assert_eq!(junk_short("fn f()->u8{1}", 2), "fn f()->u8{3;1}\n");
+    assert_eq!(junk_short("fn f(){loop{}}", 4), "fn f(){3;loop{3;}}\n");
+    assert_eq!(
+        junk_short("fn f(){let g=||{};}", 4),
+        "fn f(){3;let g=||{3;};}\n"
+    );
+    assert_eq!(
+        junk_short("fn f()->u8{if true{1}else{2}}", 6),
+        "fn f()->u8{3;if true{3;1}else{3;2}}\n"
+    );
+}
+
+/// Unformat `src` with parentheses and spelling but no junk to pad it, `by`
+/// characters wider than it is.
+fn parens_short(src: &str, by: usize) -> String {
+    let config = Config {
+        width: src.len() + by,
+        max_junk: Some(0),
+        ..Default::default()
+    };
+    unformat(src, &config)
+}
+
+#[test]
+fn type_parentheses() {
+    assert_eq!(parens_short("type T=Vec<u8>;", 4), "type T=(Vec<(u8)>);\n");
+    assert_eq!(parens_short("type T=&u8;", 4), "type T=(&(u8));\n");
+    assert_eq!(
+        parens_short("fn f(x:&u8)->u8{*x}", 4),
+        "fn f((x):(&u8))->u8{*x}\n"
+    );
+    assert_eq!(
+        parens_short("fn f<T>()where T:Clone{}", 2),
+        "fn f<T>()where (T):Clone{}\n"
+    );
+    // The closing > has to be split from the one after it
+    assert_eq!(
+        parens_short("type T=Vec<Vec<u8>>;", 4),
+        "type T=(Vec<(Vec<u8>)>);\n"
+    );
+
+    // struct S(pub (u8)) would have a visibility, constants passed as
+    // generic arguments would become types, and (!) is unstable
+    assert_eq!(
+        parens_short("struct S(pub u8);", 2),
+        "struct r#S(pub u8);\n"
+    );
+    assert_eq!(
+        parens_short("fn f<const N:usize>()->S<N>{}", 2),
+        "fn f<const N:(usize)>()->S<N>{}\n"
+    );
+    assert_eq!(
+        parens_short("fn f()->!{loop{}}", 2),
+        "fn f()->!{(loop{})}\n"
+    );
+}
+
+#[test]
+fn value_parentheses() {
+    // Four characters wider, with no junk, is a pair of parentheses around the
+    // pattern and one around the value
+    let wrap = |src: &str| parens_short(src, 4);
+    // Without parameters, since their types come first
+    assert_eq!(wrap("fn f(){let y=x;}"), "fn f(){let (y)=(x);}\n");
+    assert_eq!(wrap("fn f(){let y=p.0;}"), "fn f(){let (y)=(p.0);}\n");
+    assert_eq!(wrap("fn f(){let r=0..1;}"), "fn f(){let (r)=(0..1);}\n");
+    assert_eq!(
+        wrap("fn f(){let y=if c{1}else{2};}"),
+        "fn f(){let (y)=(if c{1}else{2});}\n"
+    );
+    assert_eq!(
+        wrap("fn f(){let y=match c{_=>1};}"),
+        "fn f(){let (y)=(match c{_=>1});}\n"
+    );
+
+    // Block-like statements would need a semicolon after parentheses, and so
+    // would an arm body without a comma
+    assert_eq!(
+        wrap("fn f(){match 1{_=>{}}let _y=1;}"),
+        "fn f(){match (1){(_)=>{}}let _y=1;}\n"
+    );
+    assert_eq!(
+        parens_short("fn f(){match x{_=>{}_=>y,}}", 10),
+        "fn f(){(match (x){(_)=>{}(_)=>(y),})}\n"
+    );
+    assert_eq!(wrap("fn f(){{}let _y=1;}"), "fn f(){{}let (_y)=(1);}\n");
+}
+
+const ITEMS: &str = "
+use std::fmt::Debug;
+
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn show(value: impl Debug) -> String {
+    format!(\"{value:?}\")
+}
+
+fn main() {
+    let point = Point { x: 1, y: 2 };
+    println!(\"{}\", show(point.x + point.y));
+}
+";
+
+#[test]
+fn per_item() {
+    let config = Config {
+        width: 30,
+        item_rectangles: true,
+        ..Default::default()
+    };
+    let out = unformat(ITEMS, &config);
+    let lines = out.lines().collect::<Vec<_>>();
+    let rectangles = lines.split(|line| line.is_empty()).collect::<Vec<_>>();
+
+    assert_eq!(rectangles.len(), 4, "{out}");
+    for (rectangle, start) in rectangles.iter().zip(["use", "struct", "fn", "fn"]) {
+        assert!(rectangle[0].starts_with(start), "{out}");
+        assert_width(rectangle, 30);
+    }
+}
+
+const FUNCTIONS: &str = "
+fn first(values: &[u32]) -> u32 {
+    let mut total = 0;
+    for value in values {
+        total += value * 2;
+    }
+    total
+}
+
+fn second(name: &str) -> String {
+    let greeting = format!(\"hello {name}\");
+    greeting.to_uppercase()
+}
+
+fn third(a: u32, b: u32) -> u32 {
+    let bigger = if a > b { a } else { b };
+    let smaller = a.min(b);
+    bigger - smaller
+}
+";
+
+const STATEMENTS: &str = "
+fn main() {
+    let a = 1;
+    let b = a + 2;
+    let c = b * 3;
+    let d = c - 4;
+    let e = d / 5;
+    let f = e % 6;
+    let g = f + a;
+    let h = g * b;
+    let i = h - c;
+    let j = i + d;
+    let k = j * e;
+    let l = k - f;
+    println!(\"{l}\");
+}
+";
+
+/// Number of lines at the start and at the end that are the same before and
+/// after an edit.
+fn unchanged(before: &str, after: &str) -> (usize, usize) {
+    let before = before.lines().collect::<Vec<_>>();
+    let after = after.lines().collect::<Vec<_>>();
+    let prefix = before
+        .iter()
+        .zip(&after)
+        .take_while(|(before, after)| before == after)
+        .count();
+    let suffix = before
+        .iter()
+        .rev()
+        .zip(after.iter().rev())
+        .take_while(|(before, after)| before == after)
+        .count();
+    (prefix, suffix)
+}
+
+#[test]
+fn stable_items() {
+    let config = Config {
+        width: 36,
+        stable: true,
+        ..Default::default()
+    };
+    let before = unformat(FUNCTIONS, &config);
+    let after = unformat(&FUNCTIONS.replace("hello", "hello there"), &config);
+    assert_ne!(before, after);
+
+    // Items always start a line, so the ones around the edit don't move
+    let (prefix, suffix) = unchanged(&before, &after);
+    let second = before.lines().position(|line| line.contains("second"));
+    let third = before.lines().position(|line| line.contains("third"));
+    assert!(
+        second.is_some_and(|second| prefix >= second),
+        "{before}\n{after}"
+    );
+    assert!(
+        third.is_some_and(|third| before.lines().count() - suffix <= third),
+        "{before}\n{after}"
+    );
+}
+
+#[test]
+fn stable_statements() {
+    let config = Config {
+        width: 36,
+        stable: true,
+        ..Default::default()
+    };
+    let before = unformat(STATEMENTS, &config);
+    let after = unformat(&STATEMENTS.replace("a + 2;", "a + 2000 * a;"), &config);
+    assert_ne!(before, after);
+
+    // Statements after the next anchor are laid out the same
+    let (prefix, suffix) = unchanged(&before, &after);
+    let lines = before.lines().count().max(after.lines().count());
+    assert!(prefix + suffix + 2 >= lines, "{before}\n{after}");
+}
+
+#[test]
+fn height() {
+    for height in [12, 20, 31] {
+        let config = Config {
+            width: 36,
+            height: Some(height),
+            ..Default::default()
+        };
+        let out = unformat(FUNCTIONS, &config);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), height, "{out}");
+        assert_width(&lines, 36);
+    }
+}
+
+#[test]
+fn height_filler() {
+    // Junk 81 characters long would end in ;; so filler lines are the longest
+    // junk without an empty statement, and a space
+    let config = Config {
+        width: 81,
+        height: Some(8),
+        ..Default::default()
+    };
+    let out = unformat(FUNCTIONS, &config);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 8, "{out}");
+    assert_width(&lines, 81);
+    let filler = format!("{} ", "if true{};".repeat(8));
+    assert!(lines.contains(&filler.as_str()), "{out}");
+    assert!(!out.contains(";;"), "{out}");
+}
+
+#[test]
+fn too_short() {
+    let config = Config {
+        width: 36,
+        height: Some(3),
+        ..Default::default()
+    };
+    assert!(cargo_unfmt::unformat_with(FUNCTIONS, &config).is_err());
+}
+
+#[test]
+fn auto_width() {
+    let search =
+        cargo_unfmt::search_width(FUNCTIONS, &Config::default(), 30..=50, Objective::Comments)
+            .unwrap();
+    assert!((30..=50).contains(&search.width));
+    assert_eq!(search.scores.len(), 21);
+    let best = search
+        .scores
+        .iter()
+        .all(|(_, score)| search.scores[search.width - 30].1 <= *score);
+    assert!(best, "{:?}", search.scores);
+
+    let out = String::from_utf8(search.unformatted).unwrap();
+    assert_width(&out.lines().collect::<Vec<_>>(), search.width);
+}
+
+#[test]
+fn auto_width_square() {
+    let search =
+        cargo_unfmt::search_width(FUNCTIONS, &Config::default(), 20..=80, Objective::SQUARE)
+            .unwrap();
+
+    // No other width comes closer to twice as many columns as lines
+    let squareness = |width: usize| {
+        let config = Config {
+            width,
+            ..Default::default()
+        };
+        let lines = unformat(FUNCTIONS, &config).lines().count();
+        (width as f64 / lines as f64 / 2.0).ln().abs()
+    };
+    let best = squareness(search.width);
+    for width in 20..=80 {
+        assert!(
+            best <= squareness(width),
+            "{width} is squarer than {}",
+            search.width
+        );
+    }
+}
+
+#[test]
+fn objective_needs_auto_width() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("objective");
+    fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-unfmt"))
+        .args(["unfmt", "--objective", "junk", "--width", "80"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn frame() {
+    let config = Config {
+        width: 40,
+        frame: true,
+        ..Default::default()
+    };
+    let out = unformat(FUNCTIONS, &config);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_width(&lines, 40);
+
+    let (edges, sides) = lines.split_at(1);
+    let (sides, edge) = sides.split_at(sides.len() - 1);
+    for edge in [edges[0], edge[0]] {
+        assert_eq!(edge, format!("/*{}*/", "*".repeat(36)));
+    }
+    for side in sides {
+        assert!(
+            side.starts_with("/*|*/") && side.ends_with("/*|*/"),
+            "{out}"
+        );
+    }
+}
+
+#[test]
+fn frame_multi_line_literal() {
+    let config = Config {
+        width: 40,
+        frame: true,
+        ..Default::default()
+    };
+    let src = "const S: &str = \"one\ntwo\";";
+    assert!(cargo_unfmt::unformat_with(src, &config).is_err());
+}
+
+#[test]
+fn acrostic() {
+    for message in ["HELLO WORLD", "unfmt", "a_b;c{d}"] {
+        let config = Config {
+            width: 40,
+            acrostic: Some(message.to_string()),
+            ..Default::default()
+        };
+        let out = unformat(FUNCTIONS, &config);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_width(&lines, 40);
+
+        // Unless the code starts with the first character, the first line
+        // only opens a comment for the second to close
+        let lines = match lines[0].starts_with(&message[..1]) {
+            true => &lines[..],
+            false => &lines[1..],
+        };
+        let spelled = lines
+            .iter()
+            .filter_map(|line| line.trim_start().chars().next())
+            .collect::<String>();
+        let message = message.replace(' ', "");
+        assert!(spelled.starts_with(&message), "{out}");
+    }
+}
+
+#[test]
+fn acrostic_multi_line_literal() {
+    let config = Config {
+        width: 40,
+        acrostic: Some("HELLOWORLD".to_string()),
+        ..Default::default()
+    };
+    let src = "fn f() -> &'static str {\n    \"one\ntwo\nthree\"\n}";
+    assert!(cargo_unfmt::unformat_with(src, &config).is_err());
+}
+
+/// Longest nesting of parentheses around any one expression.
+#[derive(Default)]
+struct Parens {
+    depth: usize,
+    max: usize,
+}
+
+impl<'ast> Visit<'ast> for Parens {
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        match i {
+            syn::Expr::Paren(paren) => {
+                self.depth += 1;
+                self.max = self.max.max(self.depth);
+                self.visit_expr(&paren.expr);
+                self.depth -= 1;
+            }
+            _ => {
+                let depth = std::mem::take(&mut self.depth);
+                visit::visit_expr(self, i);
+                self.depth = depth;
+            }
+        }
+    }
+}
+
+fn max_parens(src: &str) -> usize {
+    let mut parens = Parens::default();
+    parens.visit_file(&syn::parse_file(src).unwrap());
+    parens.max
+}
+
+/// Longest run of statements in a block that don't mention any of `names`.
+/// Junk always ends in a semicolon, so a tail expression is never junk.
+struct Junk<'a> {
+    names: &'a [&'a str],
+    max: usize,
+}
+
+impl<'ast> Visit<'ast> for Junk<'_> {
+    fn visit_block(&mut self, i: &'ast syn::Block) {
+        let mut run = 0;
+        for stmt in &i.stmts {
+            let text = stmt.to_token_stream().to_string();
+            let real = matches!(stmt, syn::Stmt::Expr(_, None))
+                || text
+                    .split(|c: char| !c.is_alphanumeric() && c != '_')
+                    .any(|word| self.names.contains(&word));
+            run = if real { 0 } else { run + 1 };
+            self.max = self.max.max(run);
+        }
+        visit::visit_block(self, i);
+    }
+}
+
+fn max_junk(src: &str, names: &[&str]) -> usize {
+    let mut junk = Junk { names, max: 0 };
+    junk.visit_file(&syn::parse_file(src).unwrap());
+    junk.max
+}
+
+const SHORT: &str = "fn main() { let x = f(1) + g(2); println!(\"{x}\"); }
+fn f(n: u32) -> u32 { n * 2 }
+fn g(n: u32) -> u32 { n + 3 }
+";
+
+#[test]
+fn limits() {
+    let names = ["x", "f", "g", "n", "println"];
+    for width in [40, 80, 120] {
+        for limit in [0, 1, 2] {
+            let config = Config {
+                width,
+                max_parens: Some(limit),
+                max_junk: Some(limit),
+                ..Default::default()
+            };
+            let out = unformat(SHORT, &config);
+            assert_width(&out.lines().collect::<Vec<_>>(), width);
+            assert!(max_parens(&out) <= limit, "{out}");
+            assert!(max_junk(&out, &names) <= limit, "{out}");
+        }
+    }
+}
+
+#[test]
+fn spread() {
+    let names = ["x", "f", "g", "n", "println"];
+    for width in [40, 80, 120] {
+        let piled = unformat(
+            SHORT,
+            &Config {
+                width,
+                ..Default::default()
+            },
+        );
+        let spread = unformat(
+            SHORT,
+            &Config {
+                width,
+                spread: true,
+                ..Default::default()
+            },
+        );
+        assert_width(&spread.lines().collect::<Vec<_>>(), width);
+
+        // Junk and parentheses take turns instead of junk going first
+        assert!(max_parens(&spread) > 0, "{spread}");
+        assert!(
+            max_junk(&spread, &names) <= max_junk(&piled, &names),
+            "{piled}\n{spread}"
+        );
+    }
+}
+
+const LONG: &str = "fn main() {
+    let greeting = \"a string literal that is longer than the narrowest width\";
+    println!(\"{greeting}\");
+}
+";
+
+#[test]
+fn overflow_error() {
+    let config = |width| Config {
+        width,
+        overflow: Overflow::Error,
+        ..Default::default()
+    };
+    assert!(cargo_unfmt::unformat_with(LONG, &config(30)).is_err());
+    assert!(cargo_unfmt::unformat_with(LONG, &config(80)).is_ok());
+}
+
+#[test]
+fn overflow_widen() {
+    let config = Config {
+        width: 30,
+        overflow: Overflow::Widen,
+        ..Default::default()
+    };
+    let out = unformat(LONG, &config);
+    let literal = LONG
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_once('='))
+        .unwrap();
+    let widest = literal.1.trim().trim_end_matches(';').len();
+    assert_width(&out.lines().collect::<Vec<_>>(), widest);
+}
+
+#[test]
+fn overflow_split() {
+    let config = Config {
+        width: 30,
+        overflow: Overflow::Split,
+        ..Default::default()
+    };
+    let out = unformat(LONG, &config);
+    assert_width(&out.lines().collect::<Vec<_>>(), 30);
+}
+
+const MULTI_LINE: &str = "fn main() {
+    let poem = \"roses are red,
+    violets are blue,
+this literal spans
+several lines\";
+    println!(\"{poem}\");
+}
+";
+
+#[test]
+fn multi_line_literal() {
+    let inner = ["    violets are blue,", "this literal spans"];
+    for width in [20, 30, 50] {
+        let config = Config {
+            width,
+            ..Default::default()
+        };
+        let out = unformat(MULTI_LINE, &config);
+        let lines = out.lines().collect::<Vec<_>>();
+
+        // Lines inside the literal stay as they are, and every other line,
+        // including the ones the literal starts and ends on, is padded
+        let start = lines.iter().position(|line| *line == inner[0]);
+        assert!(
+            start.is_some_and(|start| lines[start + 1] == inner[1]),
+            "{out}"
+        );
+        let outside = lines.iter().filter(|line| !inner.contains(line));
+        assert_width(&outside.copied().collect::<Vec<_>>(), width);
+    }
+}
+
+const STATES: &str = "
+enum State { Idle, Running(u8, u8), Done { code: u8, last: bool } }
+
+fn step(state: State, input: Option<u8>) -> State {
+    match (state, input) {
+        (State::Idle, Some(first)) => State::Running(first, first),
+        (State::Running(low, high), Some(next)) => State::Running(low.min(next), high.max(next)),
+        (State::Running(low, high), None) => State::Done { code: high - low, last: true },
+        (done, _) => done,
+    }
+}
+";
+
+#[test]
+fn patterns() {
+    assert_eq!(
+        parens_short("fn f(){let (a,b)=c;}", 4),
+        "fn f(){let(((a),b,))=c;}\n"
+    );
+    assert_eq!(
+        parens_short("fn f(){match x{(A|B,_)=>{}}}", 8),
+        "fn f(){(match (x){(((A|B),_))=>{}})}\n"
+    );
+    // A | B is only wrapped inside another pattern, 1.. and -1 would get
+    // parentheses in the wrong place, and .. isn't a pattern of its own
+    assert_eq!(
+        parens_short("fn f(){match x{-1|1..=2|3..|_=>{}}}", 6),
+        "fn f(){(match (x){-1|(1..=2)|3..|_=>{}})}\n"
+    );
+    assert_eq!(
+        parens_short("fn f(){let [a,b@..]=x;}", 4),
+        "fn f(){let([( a),b@..])=x;}\n"
+    );
+    // Nor are the parameters of a function without a body, or shorthand fields
+    assert_eq!(
+        parens_short("trait T{fn g(x:u8);}", 2),
+        "trait T{fn g(x:(u8));}\n"
+    );
+    assert_eq!(
+        parens_short("fn f(){let S{x}=s;}", 4),
+        "fn f(){let (S{x})=(s);}\n"
+    );
+
+    // Bindings can become x@_ where raw identifiers run out
+    let config = Config {
+        max_parens: Some(0),
+        ..Default::default()
+    };
+    assert!(count_widths(STATES, &config, |_, out| out.contains("@_")) > 0);
+}
+
+const DEFINITIONS: &str = "
+struct Meters(u32);
+struct Seconds(u32);
+enum Unit { Meters, Seconds }
+trait Measure { fn value(&self) -> u32; }
+impl Measure for Meters { fn value(&self) -> u32 { self.0 } }
+impl Measure for Seconds { fn value(&self) -> u32 { self.0 } }
+extern \"C\" { fn abs(input: i32) -> i32; }
+type Both = (Meters, Seconds);
+";
+
+/// Whether junk items ended up where items can't be junk.
+#[derive(Default)]
+struct Misplaced(bool);
+
+impl<'ast> Visit<'ast> for Misplaced {
+    fn visit_item_impl(&mut self, i: &'ast syn::ItemImpl) {
+        if i.trait_.is_some() {
+            self.0 |= i
+                .items
+                .iter()
+                .any(|item| matches!(item, syn::ImplItem::Const(item) if item.ident == "_"));
+        }
+        visit::visit_item_impl(self, i);
+    }
+
+    fn visit_item_foreign_mod(&mut self, i: &'ast syn::ItemForeignMod) {
+        self.0 |= i
+            .items
+            .iter()
+            .any(|item| !matches!(item, syn::ForeignItem::Fn(_)));
+        visit::visit_item_foreign_mod(self, i);
+    }
+}
+
+#[test]
+fn item_junk() {
+    let mut junk = false;
+    for width in 30..=60 {
+        let config = Config {
+            width,
+            ..Default::default()
+        };
+        let out = unformat(DEFINITIONS, &config);
+        let file = syn::parse_file(&out).unwrap();
+        let items = file.items.iter().filter(|item| match item {
+            syn::Item::Const(item) => item.ident == "_",
+            syn::Item::Impl(item) => item.items.is_empty(),
+            _ => false,
+        });
+        junk |= items.count() > 0;
+
+        let mut misplaced = Misplaced::default();
+        misplaced.visit_file(&file);
+        assert!(!misplaced.0, "{out}");
+    }
+    assert!(junk);
+}
+
+const DATA: &str = "
+enum Color { Red, Green, Blue, Custom(u8, u8, u8) }
+struct Pixel { x: u16, y: u16, color: Color, alpha: u8 }
+struct Palette(Color, Color, Color);
+fn pick(color: Color, fallback: Color) -> Color {
+    match color { Color::Red => fallback, other => other }
+}
+";
+
+/// Whether a `cfg` predicate made of `all`, `any` and `not` holds.
+fn holds(meta: &syn::Meta) -> bool {
+    let args = || {
+        meta.require_list()
+            .unwrap()
+            .parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)
+            .unwrap()
+    };
+    match meta
+        .path()
+        .get_ident()
+        .map(|ident| ident.to_string())
+        .as_deref()
+    {
+        Some("all") => args().iter().all(holds),
+        Some("any") => args().iter().any(holds),
+        Some("not") => !holds(&args()[0]),
+        _ => panic!("{} isn't a predicate", meta.to_token_stream()),
+    }
+}
+
+/// Attributes on fields, variants, parameters and arms, checked to do
+/// nothing.
+#[derive(Default)]
+struct NoOps(usize);
+
+impl NoOps {
+    fn check(&mut self, attrs: &[syn::Attribute]) {
+        for attr in attrs {
+            let args = || {
+                attr.parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)
+                    .unwrap()
+            };
+            if attr.path().is_ident("cfg") {
+                assert!(holds(&args()[0]), "{}", attr.to_token_stream());
+            } else if attr.path().is_ident("cfg_attr") {
+                assert!(!holds(&args()[0]), "{}", attr.to_token_stream());
+            } else {
+                panic!("{} isn't junk", attr.to_token_stream());
+            }
+            self.0 += 1;
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for NoOps {
+    fn visit_field(&mut self, i: &'ast syn::Field) {
+        self.check(&i.attrs);
+        visit::visit_field(self, i);
+    }
+
+    fn visit_variant(&mut self, i: &'ast syn::Variant) {
+        self.check(&i.attrs);
+        visit::visit_variant(self, i);
+    }
+
+    fn visit_pat_type(&mut self, i: &'ast syn::PatType) {
+        self.check(&i.attrs);
+        visit::visit_pat_type(self, i);
+    }
+
+    fn visit_arm(&mut self, i: &'ast syn::Arm) {
+        self.check(&i.attrs);
+        visit::visit_arm(self, i);
+    }
+}
+
+#[test]
+fn attribute_junk() {
+    assert_eq!(
+        junk_short("struct S{x:u8}", 21),
+        "impl S{}struct S{#[cfg(all())]x:u8}\n"
+    );
+
+    let padded = count_widths(DATA, &Config::default(), |_, out| {
+        let mut no_ops = NoOps::default();
+        no_ops.visit_file(&syn::parse_file(out).unwrap());
+        no_ops.0 > 0
+    });
+    assert!(padded > 0);
+}
+
+#[test]
+fn literal_padding() {
+    let pad = |src: &str, by| short(src, by);
+    assert_eq!(pad("const _:()={1;};", 1), "const _:()={01;};\n");
+    assert_eq!(pad("const _:()={1;};", 3), "const _:()={0__1;};\n");
+    assert_eq!(pad("const _:()={0x1;};", 2), "const _:()={0x__1;};\n");
+    assert_eq!(pad("const _:()={1u8;};", 2), "const _:()={0_1u8;};\n");
+    assert_eq!(pad("const _:()={\"x\";};", 1), "const _:()={r\"x\";};\n");
+    assert_eq!(pad("const _:()={\"x\";};", 3), "const _:()={r#\"x\"#;};\n");
+    assert_eq!(pad("const _:()={r\"x\";};", 2), "const _:()={r#\"x\"#;};\n");
+    assert_eq!(pad("const _:()={b\"x\";};", 1), "const _:()={br\"x\";};\n");
+    assert_eq!(
+        pad("const _:()=match 1{1=>{}_=>{}};", 2),
+        "const _:()=match 01{01=>{}_=>{}};\n"
+    );
+    // Escapes would mean something else in a raw string, and attributes and
+    // macros can look at how a literal is spelled
+    for src in [
+        "const _:()={\"\\n\";};",
+        "const _:()={'x';};",
+        "#[doc=\"x\"]const _:()={};",
+        "const _:()={stringify!(1);};",
+    ] {
+        let out = one_short(src);
+        assert_eq!(out.trim_end().replace(' ', ""), src.replace(' ', ""));
+    }
+}
+
+const LITERALS: &str = r##"
+fn main() {
+    let n = 1 + 0x1f + 0o7 + 0b1 + 1_000 + 7u64 as u32;
+    let f = 1.5 + 2e3;
+    let s = ["plain", "quote\"", "tab\t", r"raw", r#"hash"#];
+    let b = [b"bytes" as &[u8], br"raw"];
+    match n {
+        0 => {}
+        1..=9 => {}
+        _ => println!("{n} {f} {s:?} {b:?}"),
+    }
+}
+"##;
+
+/// The value and spelling of every literal in a file, in order.
+#[derive(Default)]
+struct Values(Vec<(String, String)>);
+
+impl Visit<'_> for Values {
+    fn visit_lit(&mut self, i: &syn::Lit) {
+        let spelling = i.to_token_stream().to_string();
+        let value = match i {
+            syn::Lit::Str(s) => format!("{:?}", s.value()),
+            syn::Lit::ByteStr(s) => format!("{:?}", s.value()),
+            syn::Lit::Int(i) => format!("{}{}", i.base10_parse::<u128>().unwrap(), i.suffix()),
+            syn::Lit::Float(f) => format!("{}{}", f.base10_parse::<f64>().unwrap(), f.suffix()),
+            _ => spelling.clone(),
+        };
+        self.0.push((value, spelling));
+    }
+}
+
+fn values(src: &str) -> Vec<(String, String)> {
+    let mut values = Values::default();
+    values.visit_file(&syn::parse_file(src).unwrap());
+    values.0
+}
+
+#[test]
+fn literal_values() {
+    let expected = values(LITERALS);
+    let config = Config {
+        // Junk has literals of its own
+        max_junk: Some(0),
+        ..Default::default()
+    };
+    let respelled = count_widths(LITERALS, &config, |width, out| {
+        let actual = values(out);
+        assert_eq!(actual.len(), expected.len(), "width {width}:\n{out}");
+        let mut respelled = false;
+        for ((value, spelling), (expected, original)) in actual.iter().zip(&expected) {
+            assert_eq!(value, expected, "{spelling} isn't {original}");
+            respelled |= spelling != original;
+        }
+        respelled
+    });
+    assert!(respelled > 0);
+}
+
+/// Every identifier in `tokens`, including ones in macro input.
+fn idents(tokens: proc_macro2::TokenStream, out: &mut Vec<String>) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Ident(ident) => out.push(ident.to_string()),
+            proc_macro2::TokenTree::Group(group) => idents(group.stream(), out),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn raw_identifiers() {
+    let src = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/identifiers.rs"),
+    )
+    .unwrap();
+    let mut original = Vec::new();
+    idents(src.parse().unwrap(), &mut original);
+
+    let raw = count_widths(&src, &Config::default(), |width, out| {
+        // Lifetimes and labels can't be raw
+        assert!(!out.contains("'r#"), "{out}");
+
+        let mut actual = Vec::new();
+        idents(out.parse().unwrap(), &mut actual);
+        // Junk can add identifiers, but never takes any away
+        let mut rest = actual.iter();
+        let mut raw = false;
+        for ident in &original {
+            let made_raw = format!("r#{ident}");
+            let found = rest.find(|&actual| actual == ident || *actual == made_raw);
+            assert!(
+                found.is_some(),
+                "{ident} is missing at width {width}:\n{out}"
+            );
+            let found = found.unwrap();
+            if found != ident {
+                raw = true;
+                assert!(
+                    !["self", "Self", "super", "crate", "_", "macro_rules"]
+                        .contains(&ident.as_str()),
+                    "{found} at width {width}:\n{out}"
+                );
+            }
+        }
+        raw
+    });
+    assert!(raw > 0);
+
+    // Lifetimes, _, self and Self stay as they are
+    assert_eq!(
+        short("fn f<'a>(x:&'a u8,_:u8){}", 6),
+        "fn r#f<'a>(r#x:&'a r#u8,_:u8){}\n"
+    );
+    assert_eq!(
+        short("impl S{fn f(self)->Self{self}}", 4),
+        "impl r#S{fn r#f(self)->Self{self}}\n"
+    );
+}
+
+const FIELDS: &str = "
+struct Point { x: u8, y: u8 }
+fn flip(point: Point) -> Point {
+    let Point { x, y } = point;
+    match (Point { x: y, y: x }) {
+        Point { x, y } => Point { x, y },
+    }
+}
+";
+
+/// Shorthand fields that were spelled out, in patterns and in values.
+#[derive(Default)]
+struct SpelledOut {
+    patterns: usize,
+    values: usize,
+}
+
+impl Visit<'_> for SpelledOut {
+    fn visit_field_pat(&mut self, i: &syn::FieldPat) {
+        if let (syn::Member::Named(name), syn::Pat::Ident(pat)) = (&i.member, &*i.pat) {
+            self.patterns += usize::from(i.colon_token.is_some() && pat.ident == *name);
+        }
+        visit::visit_field_pat(self, i);
+    }
+
+    fn visit_field_value(&mut self, i: &syn::FieldValue) {
+        let value = i.expr.to_token_stream().to_string();
+        if let syn::Member::Named(name) = &i.member {
+            // The value can have parentheses around it
+            let value = value.trim_matches(|c| c == '(' || c == ')' || c == ' ');
+            self.values += usize::from(i.colon_token.is_some() && name == value);
+        }
+        visit::visit_field_value(self, i);
+    }
+}
+
+#[test]
+fn shorthand_fields() {
+    assert_eq!(short("fn f(){P{x}}", 2), "fn f(){P{x:x}}\n");
+    // Only values are spelled out, and patterns stay as they were written
+    assert_eq!(short("fn f(){let P{x}=p;}", 3), "fn r#f(){let P{x,}=p;}\n");
+
+    let mut spelled_out = SpelledOut::default();
+    let padded = count_widths(FIELDS, &Config::default(), |_, out| {
+        let values = spelled_out.values;
+        spelled_out.visit_file(&syn::parse_file(out).unwrap());
+        spelled_out.values > values
+    });
+    assert_eq!(spelled_out.patterns, 0);
+    assert!(padded > 0);
+}
+
+#[test]
+fn integer_methods() {
+    let src = "fn main(){let _=1.cmp(&2);let _=0x1.max(2);let _=1.0.max(2.0);}";
+    for width in 8..=40 {
+        let config = Config {
+            width,
+            ..Default::default()
+        };
+        let out = unformat(src, &config);
+        assert!(syn::parse_file(&out).is_ok(), "{out}");
+    }
+}
+
+/// Every import in a file, with the attributes and visibility it has and the
+/// module it's in, and how many `use` declarations there are.
+#[derive(Default)]
+struct Imports {
+    module: Vec<String>,
+    imports: Vec<String>,
+    declarations: usize,
+}
+
+impl Imports {
+    fn leaves(&mut self, prefix: &str, tree: &syn::UseTree, path: String) {
+        match tree {
+            syn::UseTree::Path(tree) => self.leaves(
+                prefix,
+                &tree.tree,
+                format!("{path}{}::", tree.ident.unraw()),
+            ),
+            syn::UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.leaves(prefix, tree, path.clone());
+                }
+            }
+            syn::UseTree::Name(name) => self
+                .imports
+                .push(format!("{prefix}{path}{}", name.ident.unraw())),
+            syn::UseTree::Rename(rename) => self.imports.push(format!(
+                "{prefix}{path}{} as {}",
+                rename.ident.unraw(),
+                rename.rename.unraw()
+            )),
+            syn::UseTree::Glob(_) => self.imports.push(format!("{prefix}{path}*")),
+        }
+    }
+}
+
+impl Visit<'_> for Imports {
+    fn visit_item_mod(&mut self, i: &syn::ItemMod) {
+        self.module.push(i.ident.to_string());
+        visit::visit_item_mod(self, i);
+        self.module.pop();
+    }
+
+    fn visit_item_use(&mut self, i: &syn::ItemUse) {
+        self.declarations += 1;
+        let attrs = i
+            .attrs
+            .iter()
+            .map(|attr| attr.to_token_stream().to_string())
+            .collect::<String>();
+        let prefix = format!(
+            "{} {attrs} {} {}",
+            self.module.join("::"),
+            i.vis.to_token_stream(),
+            if i.leading_colon.is_some() { "::" } else { "" },
+        );
+        self.leaves(&prefix, &i.tree, String::new());
+    }
+}
+
+fn imports(src: &str) -> Imports {
+    let mut imports = Imports::default();
+    imports.visit_file(&syn::parse_file(src).unwrap());
+    imports.imports.sort();
+    imports
+}
+
+#[test]
+fn regroup() {
+    let src =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/imports.rs"))
+            .unwrap();
+    let original = imports(&src);
+    let config = Config {
+        regroup: true,
+        ..Default::default()
+    };
+    let regrouped = count_widths(&src, &config, |width, out| {
+        let out = imports(out);
+        assert_eq!(out.imports, original.imports, "width {width}");
+        out.declarations != original.declarations
+    });
+    assert!(regrouped > 0);
+
+    // Without --regroup, imports stay as they were written
+    let kept = count_widths(&src, &Config::default(), |_, out| {
+        imports(out).declarations == original.declarations
+    });
+    assert_eq!(kept, WIDTHS.count());
+
+    // Declarations can be merged to make room, or split to fill it
+    let regroup = |src: &str, by| {
+        let config = Config {
+            width: src.len() + by,
+            max_parens: Some(0),
+            max_junk: Some(0),
+            regroup: true,
+            ..Default::default()
+        };
+        unformat(src, &config)
+    };
+    assert_eq!(regroup("use a::b;use a::c;", 0), "use r#a::{ r#b,c};\n");
+    assert_eq!(regroup("use a::{b,c};", 6), "use a::b; use a::c;\n");
+}
+
+/// The first identifier in `tokens` that isn't an item keyword, looking inside
+/// groups, since `impl Counter` can become `impl (Counter)`.
+fn first_name(tokens: proc_macro2::TokenStream) -> Option<String> {
+    tokens.into_iter().find_map(|token| match token {
+        proc_macro2::TokenTree::Ident(ident)
+            if ![
+                "pub", "const", "static", "struct", "type", "fn", "impl", "enum", "trait",
+            ]
+            .contains(&ident.unraw().to_string().as_str()) =>
+        {
+            Some(ident.unraw().to_string())
+        }
+        proc_macro2::TokenTree::Group(group) => first_name(group.stream()),
+        _ => None,
+    })
+}
+
+/// The top-level items of `src` between item macros, each sorted, with the
+/// macros themselves between them, and the items in the order they're in.
+fn item_runs(src: &str) -> (Vec<Vec<String>>, Vec<String>) {
+    let file = syn::parse_file(src).unwrap();
+    let name = |item: &syn::Item| match item {
+        syn::Item::Macro(item) => format!("{}!", item.ident.as_ref().unwrap().unraw()),
+        item => first_name(item.to_token_stream()).unwrap(),
+    };
+    let order = file.items.iter().map(name).collect::<Vec<_>>();
+    let mut runs = vec![vec![]];
+    for item in &file.items {
+        match item {
+            syn::Item::Macro(_) => runs.extend([vec![name(item)], vec![]]),
+            item => runs.last_mut().unwrap().push(name(item)),
+        }
+    }
+    for run in &mut runs {
+        run.sort();
+    }
+    (runs, order)
+}
+
+#[test]
+fn reorder() {
+    let src =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/reordered.rs"))
+            .unwrap();
+    let (runs, order) = item_runs(&src);
+    let config = Config {
+        reorder: true,
+        // Junk items would be new items
+        max_junk: Some(0),
+        ..Default::default()
+    };
+    let reordered = count_widths(&src, &config, |width, out| {
+        // Inner attributes stay first
+        assert!(out.starts_with("#!["), "{out}");
+        // Items only move between the macros around them
+        let (out_runs, out_order) = item_runs(out);
+        assert_eq!(out_runs, runs, "width {width}:\n{out}");
+        out_order != order
+    });
+    assert!(reordered > 0);
+
+    // A longer item can go first to fill a line
+    let config = Config {
+        width: 16,
+        max_parens: Some(0),
+        max_junk: Some(0),
+        reorder: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        unformat("fn a(){}fn bbbbbb(){}fn c(){}", &config),
+        "fn bbbbbb() {}fn\nr#a(){} fn c(){}\n"
+    );
+}
+
+/// Arguments of standard library macros that were wrapped in parentheses or
+/// a block, and format strings that were touched.
+#[derive(Default)]
+struct MacroArgs {
+    wrapped: usize,
+    format_strings: Vec<String>,
+}
+
+impl Visit<'_> for MacroArgs {
+    fn visit_macro(&mut self, i: &syn::Macro) {
+        let name = i.path.segments.last().unwrap().ident.unraw().to_string();
+        let parser = Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
+        let Ok(args) = i.parse_body_with(parser) else {
+            return;
+        };
+        for arg in &args {
+            match arg {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) if s.value().contains('{') => self.format_strings.push(s.token().to_string()),
+                syn::Expr::Paren(_) | syn::Expr::Block(_) if name != "matches" => self.wrapped += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+fn macro_args(src: &str) -> MacroArgs {
+    let mut args = MacroArgs::default();
+    args.visit_file(&syn::parse_file(src).unwrap());
+    args
+}
+
+#[test]
+fn std_macro_arguments() {
+    let src = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/std_macros.rs"),
+    )
+    .unwrap();
+    let original = macro_args(&src);
+    assert_eq!(original.wrapped, 0);
+
+    let wrapped = count_widths(&src, &Config::default(), |width, out| {
+        let out = macro_args(out);
+        // Format strings are read by the macro, so they stay as written
+        assert_eq!(out.format_strings, original.format_strings, "width {width}");
+        out.wrapped > 0
+    });
+    assert!(wrapped > 0);
+
+    assert_eq!(
+        parens_short("fn f(){println!(\"{}\",x);}", 2),
+        "fn f(){println!(\"{}\",(x));}\n"
+    );
+    assert_eq!(
+        parens_short("fn f(){write!(w,\"{}\",x);}", 4),
+        "fn f(){write!((w),\"{}\",(x));}\n"
+    );
+    // assert! prints its argument as it's written
+    assert_eq!(
+        parens_short("fn f(){assert!(x);}", 2),
+        "fn r#f(){assert!(x);}\n"
+    );
+    assert_eq!(
+        one_short("fn f(x:u8){println!(\"{}\",x)}"),
+        "fn f(x:u8){println!(\"{}\",x,)}\n"
+    );
+    // A macro with the name of one from the standard library can take
+    // anything
+    assert_eq!(
+        short("macro_rules! vec{($e:expr)=>{}}fn f(x:u8){vec![x];}", 2),
+        "macro_rules!r#vec{($e:expr)=>{}}fn f (x:u8){vec![x];}\n"
+    );
+}
+
+/// The tokens of `tokens` as rustc sees them, where punctuation only goes
+/// together if it was written together and makes one token.
+fn glued(tokens: proc_macro2::TokenStream) -> String {
+    const COMPOUND: [&str; 22] = [
+        "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "^=",
+        "&=", "|=", "<<", ">>", "..", "...", "..=",
+    ];
+    let mut out: Vec<String> = vec![];
+    let mut joint = false;
+    for token in tokens {
+        let text = match &token {
+            proc_macro2::TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    proc_macro2::Delimiter::Parenthesis => ("(", ")"),
+                    proc_macro2::Delimiter::Bracket => ("[", "]"),
+                    proc_macro2::Delimiter::Brace => ("{", "}"),
+                    proc_macro2::Delimiter::None => ("", ""),
+                };
+                format!("{open}{}{close}", glued(group.stream()))
+            }
+            token => token.to_string(),
+        };
+        match out.last_mut() {
+            Some(last) if joint && COMPOUND.contains(&format!("{last}{text}").as_str()) => {
+                last.push_str(&text)
+            }
+            _ => out.push(text),
+        }
+        joint = matches!(&token, proc_macro2::TokenTree::Punct(punct) if punct.spacing() == proc_macro2::Spacing::Joint);
+    }
+    out.join(" ")
+}
+
+/// The matchers and transcribers of every `macro_rules!`, and the input of
+/// every call to one.
+#[derive(Default)]
+struct MacroRules {
+    names: Vec<String>,
+    matchers: Vec<String>,
+    transcribers: Vec<String>,
+    calls: Vec<String>,
+}
+
+impl Visit<'_> for MacroRules {
+    fn visit_item_macro(&mut self, i: &syn::ItemMacro) {
+        let Some(name) = &i.ident else {
+            return;
+        };
+        self.names.push(name.unraw().to_string());
+        let trees = i.mac.tokens.clone().into_iter().collect::<Vec<_>>();
+        for rule in trees.split(|tree| tree.to_string() == ";") {
+            if let [matcher, _, _, transcriber] = rule {
+                self.matchers.push(glued(matcher.clone().into()));
+                self.transcribers.push(glued(transcriber.clone().into()));
+            }
+        }
+    }
+
+    fn visit_macro(&mut self, i: &syn::Macro) {
+        let name = i.path.segments.last().unwrap().ident.unraw().to_string();
+        if self.names.contains(&name) {
+            self.calls.push(glued(i.tokens.clone()));
+        }
+    }
+}
+
+fn macro_rules(src: &str) -> MacroRules {
+    let mut rules = MacroRules::default();
+    rules.visit_file(&syn::parse_file(src).unwrap());
+    rules
+}
+
+#[test]
+fn macro_rules_transcribers() {
+    let src =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/macro_dsl.rs"))
+            .unwrap();
+    let original = macro_rules(&src);
+    let padded = count_widths(&src, &Config::default(), |width, out| {
+        let out = macro_rules(out);
+        // Matchers and calls are matched token by token, so they keep every
+        // token, and keep apart the ones that were apart
+        assert_eq!(out.matchers, original.matchers, "width {width}");
+        assert_eq!(out.calls, original.calls, "width {width}");
+        out.transcribers != original.transcribers
+    });
+    assert!(padded > 0);
+
+    assert_eq!(
+        parens_short("macro_rules!m{($e:expr)=>{$e+1}}", 4),
+        "macro_rules!m{($e:expr)=>{($e)+(1)}}\n"
+    );
+}